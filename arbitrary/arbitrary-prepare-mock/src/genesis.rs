@@ -0,0 +1,115 @@
+//! Loading of the genesis spec from a JSON file.
+//!
+//! The format loosely follows the geth genesis file:
+//!
+//! ```json
+//! {
+//!   "chainId": 1337,
+//!   "number": 0,
+//!   "timestamp": 0,
+//!   "gasLimit": "1000000",
+//!   "extraData": "0x",
+//!   "admin": "0xa151bc9b5ea30fe3922a9031c48b812bb43401392c037381aca934f4069c0517",
+//!   "alloc": {
+//!     "0xb8a0722ae6cb48cde0b4ae1f1a642f0e3c3af545e7acbd38b07251b3990914f1": { "balance": "100" }
+//!   }
+//! }
+//! ```
+//!
+//! The gas limit and the balances are decimal, or hexadecimal if prefixed with `0x`. The admin is
+//! optional, and so are the number and the timestamp, which default to zero.
+
+use arbitrary_state_machine::{Gas, GenesisSpec, H256, U256};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct GenesisFile {
+    chain_id: u64,
+    #[serde(default)]
+    number: u64,
+    #[serde(default)]
+    timestamp: u64,
+    gas_limit: String,
+    #[serde(default)]
+    extra_data: String,
+    admin: Option<String>,
+    alloc: BTreeMap<String, AllocEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AllocEntry {
+    balance: String,
+}
+
+/// Reads the genesis spec from the JSON file at the given path.
+pub fn load(path: &Path) -> Result<GenesisSpec, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&json)
+}
+
+/// Parses the genesis spec from JSON.
+pub fn parse(json: &str) -> Result<GenesisSpec, String> {
+    let file: GenesisFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let extra_data =
+        hexutil::read_hex(&file.extra_data).map_err(|e| format!("invalid extra data: {:?}", e))?;
+    let gas_limit = parse_quantity(&file.gas_limit)
+        .ok_or_else(|| format!("invalid gas limit: {}", file.gas_limit))?;
+    let admin = match file.admin {
+        Some(admin) => admin
+            .parse()
+            .map_err(|e| format!("invalid admin {}: {:?}", admin, e))?,
+        None => H256::zero(),
+    };
+    let mut alloc = BTreeMap::new();
+    for (address, entry) in file.alloc {
+        let balance = parse_quantity(&entry.balance)
+            .ok_or_else(|| format!("invalid balance of {}: {}", address, entry.balance))?;
+        let address: H256 = address
+            .parse()
+            .map_err(|e| format!("invalid address {}: {:?}", address, e))?;
+        if alloc.insert(address, balance).is_some() {
+            return Err(format!("duplicate allocation for 0x{:?}", address));
+        }
+    }
+    Ok(GenesisSpec {
+        chain_id: file.chain_id,
+        number: file.number,
+        timestamp: file.timestamp,
+        gas_limit: Gas::from(gas_limit),
+        extra_data,
+        admin,
+        alloc,
+    })
+}
+
+/// Parses a decimal or a `0x`-prefixed hexadecimal quantity, which must have at least one digit.
+fn parse_quantity(s: &str) -> Option<U256> {
+    match s.strip_prefix("0x") {
+        Some(hex) if !hex.is_empty() && hex.len() <= 64 => hex.parse().ok(),
+        Some(_) => None,
+        None if !s.is_empty() => U256::from_dec_str(s).ok(),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_matches_test_genesis() {
+        let json = include_str!("../genesis.json");
+        assert_eq!(parse(json).unwrap(), GenesisSpec::test());
+
+        // A blank or truncated quantity is not taken as zero.
+        for balance in ["", "0x"] {
+            let blank = format!(r#""balance": "{}""#, balance);
+            let json = json.replace(r#""balance": "100""#, &blank);
+            assert!(parse(&json).is_err());
+        }
+    }
+}
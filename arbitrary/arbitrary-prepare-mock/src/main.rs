@@ -1,68 +1,107 @@
+mod genesis;
+
 use arbitrary_state_machine::{
-    apply_txn, build_genesis, Backend, Block, InMemoryBackend, State, Txn, ALICE, BOB, CHARLIE,
-    DAVE, EVE, H256,
+    address, deposits_hash, execute_with_diff, keccak256, output_root, receipts_trie, txns_trie,
+    validate_and_execute, Account, BatchTxn, Block, Deposit, GenesisSpec, Header, InMemoryBackend,
+    Output, RecordingBackend, State, StateDiff, StoreTxn, TimeLock, Txn, TxnEnvelope, Witness,
+    ALICE, ALICE_SECRET, BOB, BOB_SECRET, CHARLIE, CHARLIE_SECRET, DAVE, DAVE_SECRET,
+    EMPTY_TRIE_HASH, EVE, EVE_SECRET, H256, NATIVE_ASSET, WITHDRAWALS_ADDRESS,
 };
-use std::cell::RefCell;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+/// The fee paid by every transaction in the demo chain.
+const FEE: u64 = 1;
+
+/// The number of seconds between two blocks in the demo chain.
+const BLOCK_TIME: u64 = 12;
+
+/// The account that collects the fees of all blocks in the demo chain.
+const BENEFICIARY: H256 = EVE;
+
 struct MockBlockchain {
     blocks: Vec<Block>,
+    // The changes made by each block, indexed like the blocks. The genesis made none.
+    diffs: Vec<StateDiff>,
     states: BTreeMap<H256, State<InMemoryBackend>>,
 }
 
 impl MockBlockchain {
-    /// Creates an empty blockchain with a genesis block.
-    pub fn new() -> Self {
-        let (block0, state0) = build_genesis();
+    /// Creates an empty blockchain with the genesis block described by the given spec.
+    pub fn new(genesis: &GenesisSpec) -> Self {
+        let (block0, state0) = genesis.build();
         let root0 = state0.root();
         Self {
             blocks: vec![block0],
+            diffs: vec![StateDiff::default()],
             states: vec![(root0, state0)].into_iter().collect(),
         }
     }
 
-    /// Adds a new block with the given transactions. Returns the number of the newly created block.
+    /// Adds a new block with the given deposits and transactions. Returns the index of the newly
+    /// created block, which differs from its number if the genesis number is not zero.
     /// Panics, if any of the transactions is invalid.
-    pub fn new_block(&mut self, txns: Vec<Txn>) -> usize {
+    pub fn new_block(&mut self, deposits: Vec<Deposit>, txns: Vec<TxnEnvelope>) -> usize {
         let block_num = self.blocks.len();
+        let number = self.best_block().header.number + 1;
         let parent = self.best_block().hash();
-        let mut state = State::clone(&self.state_at(self.best_block().state_root));
-        for txn in &txns {
-            apply_txn(&mut state, txn).unwrap();
-        }
-        let root = state.root();
-        self.blocks.push(Block {
-            number: block_num as u64,
-            parent,
-            state_root: root,
+        let mut state = State::clone(self.state_at(self.best_block().header.state_root));
+        let (txns_root, _) = txns_trie(&txns);
+        let mut block = Block {
+            header: Header {
+                chain_id: self.best_block().header.chain_id,
+                number,
+                timestamp: self.best_block().header.timestamp + BLOCK_TIME,
+                parent,
+                state_root: H256::zero(),
+                beneficiary: BENEFICIARY,
+                gas_limit: self.best_block().header.gas_limit,
+                deposits_hash: deposits_hash(&deposits),
+                txns_root,
+                receipts_root: EMPTY_TRIE_HASH,
+                withdrawals_root: EMPTY_TRIE_HASH,
+                extra_data: Vec::new(),
+            },
+            deposits,
             txns,
-        });
+        };
+        let (receipts, diff) = execute_with_diff(&mut state, &block).unwrap();
+        let root = state.root();
+        block.header.state_root = root;
+        block.header.receipts_root = receipts_trie(&receipts).0;
+        block.header.withdrawals_root = state.withdrawals_root();
+        self.blocks.push(block);
+        self.diffs.push(diff);
         self.states.insert(root, state);
         block_num
     }
 
-    pub fn record_trie_nodes(&self, block_num: usize) -> BTreeMap<H256, Vec<u8>> {
+    pub fn record_witness(&self, block_num: usize) -> Witness {
         if block_num == 0 {
             panic!("cannot record trie nodes for genesis block");
         }
         let block = &self.blocks[block_num];
         let parent_block = &self.blocks[block_num - 1];
-        let pre_state = State::clone(&self.state_at(parent_block.state_root));
+        let pre_state = State::clone(self.state_at(parent_block.header.state_root));
         let recording_backend = RecordingBackend::new(pre_state.into_backend());
-        let mut state = State::with_root(recording_backend, parent_block.state_root);
-        for txn in &block.txns {
-            apply_txn(&mut state, txn).unwrap();
-        }
-        assert_eq!(state.root(), block.state_root);
-        let (_, nodes) = state.into_backend().into_inner();
-        nodes
+        let mut state = State::with_roots(
+            recording_backend,
+            parent_block.header.state_root,
+            parent_block.header.withdrawals_root,
+        );
+        validate_and_execute(&mut state, &parent_block.header, block).unwrap();
+        let (_, witness) = state.into_backend().into_inner();
+        witness
     }
 
     pub fn block(&self, block_num: usize) -> &Block {
         &self.blocks[block_num]
     }
 
+    pub fn diff(&self, block_num: usize) -> &StateDiff {
+        &self.diffs[block_num]
+    }
+
     pub fn best_block_num(&self) -> usize {
         self.blocks.len() - 1
     }
@@ -76,56 +115,161 @@ impl MockBlockchain {
     }
 }
 
-fn demo_blockchain() -> MockBlockchain {
-    let mut blockchain = MockBlockchain::new();
-    blockchain.new_block(vec![Txn::new(ALICE, BOB, 13), Txn::new(BOB, ALICE, 37)]);
-    blockchain.new_block(vec![
-        Txn::new(ALICE, ALICE, 2),
-        Txn::new(BOB, ALICE, 2),
-        Txn::new(EVE, ALICE, 8),
-    ]);
-    blockchain.new_block(vec![
-        Txn::new(DAVE, ALICE, 1),
-        Txn::new(DAVE, ALICE, 1),
-        Txn::new(DAVE, ALICE, 1),
-        Txn::new(BOB, DAVE, 2),
-    ]);
-    blockchain.new_block(vec![Txn::new(CHARLIE, ALICE, 1)]);
-    blockchain
+/// Creates a transfer on the given chain signed by the given secret key.
+fn transfer(chain_id: u64, secret: &[u8; 32], nonce: u64, to: H256, value: u64) -> TxnEnvelope {
+    transfer_asset(chain_id, secret, nonce, to, NATIVE_ASSET, value)
 }
 
-/// A wrapper backend that records all the accessed nodes.
-struct RecordingBackend<B> {
-    inner: B,
-    // The accessed nodes.
-    // The key is the node hash, the value is the node preimage.
-    // RefCell is required here because the backend self is passed via a shared reference.
-    nodes: RefCell<BTreeMap<H256, Vec<u8>>>,
+/// Like [`transfer`], but for the given asset.
+fn transfer_asset(
+    chain_id: u64,
+    secret: &[u8; 32],
+    nonce: u64,
+    to: H256,
+    asset: H256,
+    value: u64,
+) -> TxnEnvelope {
+    Txn::new(chain_id, address(secret), nonce, to, value.into(), FEE)
+        .with_asset(asset)
+        .sign(secret)
+        .into()
 }
 
-impl<B> RecordingBackend<B> {
-    fn new(inner: B) -> Self {
-        RecordingBackend {
-            inner,
-            nodes: RefCell::new(BTreeMap::new()),
-        }
-    }
+/// Creates a batch transaction paying the given recipients, signed by the given secret key.
+fn batch(chain_id: u64, secret: &[u8; 32], nonce: u64, outputs: &[(H256, u64)]) -> TxnEnvelope {
+    let outputs = outputs
+        .iter()
+        .map(|&(to, value)| Output {
+            to,
+            value: value.into(),
+        })
+        .collect();
+    BatchTxn::new(chain_id, address(secret), nonce, outputs, FEE)
+        .sign(secret)
+        .into()
+}
 
-    fn into_inner(self) -> (B, BTreeMap<H256, Vec<u8>>) {
-        (self.inner, self.nodes.into_inner())
-    }
+/// Builds the demo chain on top of the given genesis. The genesis must fund the test accounts and
+/// make DAVE the admin.
+fn demo_blockchain(genesis: &GenesisSpec) -> MockBlockchain {
+    let mut blockchain = MockBlockchain::new(genesis);
+    let chain_id = genesis.chain_id;
+    blockchain.new_block(
+        vec![],
+        vec![
+            transfer(chain_id, &ALICE_SECRET, 0, BOB, 13),
+            transfer(chain_id, &BOB_SECRET, 0, ALICE, 37),
+        ],
+    );
+    blockchain.new_block(
+        vec![],
+        vec![
+            transfer(chain_id, &ALICE_SECRET, 1, ALICE, 2),
+            transfer(chain_id, &BOB_SECRET, 1, ALICE, 2),
+            transfer(chain_id, &EVE_SECRET, 0, ALICE, 8),
+        ],
+    );
+    // CHARLIE gets a deposit from L1 and spends it right away.
+    blockchain.new_block(
+        vec![Deposit {
+            to: CHARLIE,
+            value: 100.into(),
+        }],
+        vec![
+            transfer(chain_id, &DAVE_SECRET, 0, ALICE, 1),
+            transfer(chain_id, &DAVE_SECRET, 1, ALICE, 1),
+            transfer(chain_id, &DAVE_SECRET, 2, ALICE, 1),
+            transfer(chain_id, &BOB_SECRET, 2, DAVE, 2),
+            transfer(chain_id, &CHARLIE_SECRET, 0, DAVE, 150),
+        ],
+    );
+    // ALICE withdraws some of her funds to L1.
+    blockchain.new_block(
+        vec![],
+        vec![
+            transfer(chain_id, &CHARLIE_SECRET, 1, ALICE, 1),
+            transfer(chain_id, &ALICE_SECRET, 2, WITHDRAWALS_ADDRESS, 10),
+        ],
+    );
+    // DAVE, the admin, mints some gold to BOB who passes a part of it on.
+    let gold = keccak256(b"gold");
+    blockchain.new_block(
+        vec![],
+        vec![
+            transfer_asset(chain_id, &DAVE_SECRET, 3, BOB, gold, 100),
+            transfer_asset(chain_id, &BOB_SECRET, 3, EVE, gold, 25),
+        ],
+    );
+    // EVE pays everyone else out in one go. CHARLIE has signed a transfer to ALICE in advance
+    // that can't be included before this block.
+    let unlock_at = genesis.timestamp + 6 * BLOCK_TIME;
+    blockchain.new_block(
+        vec![],
+        vec![
+            batch(
+                chain_id,
+                &EVE_SECRET,
+                1,
+                &[(ALICE, 3), (BOB, 3), (CHARLIE, 3), (DAVE, 3)],
+            ),
+            Txn::new(chain_id, CHARLIE, 2, ALICE, 5.into(), FEE)
+                .with_lock(TimeLock::Timestamp(unlock_at))
+                .sign(&CHARLIE_SECRET)
+                .into(),
+        ],
+    );
+    // BOB keeps a note in his storage.
+    blockchain.new_block(
+        vec![],
+        vec![StoreTxn::new(
+            chain_id,
+            BOB,
+            4,
+            keccak256(b"note"),
+            b"thanks for the gold".to_vec(),
+            FEE,
+        )
+        .sign(&BOB_SECRET)
+        .into()],
+    );
+    blockchain
 }
 
-impl<B: Backend> Backend for RecordingBackend<B> {
-    fn get(&self, key: H256) -> Option<&[u8]> {
-        let r = self.inner.get(key);
-        self.nodes
-            .borrow_mut()
-            .insert(key, r.unwrap_or_default().to_vec());
-        r
+fn print_diff(diff: &StateDiff) {
+    println!("  changes:");
+    for (address, change) in &diff.accounts {
+        let balance = |account: &Option<Account>| account.clone().unwrap_or_default().balance;
+        let what = match (&change.old, &change.new) {
+            (None, _) => "created",
+            (_, None) => "deleted",
+            _ => "changed",
+        };
+        println!(
+            "    {} {:?}: {} -> {}",
+            what,
+            address,
+            balance(&change.old),
+            balance(&change.new)
+        );
+    }
+    for ((address, asset), change) in &diff.assets {
+        println!(
+            "    asset {:?} of {:?}: {} -> {}",
+            asset, address, change.old, change.new
+        );
     }
-    fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, removes: BTreeSet<H256>) {
-        self.inner.apply_changes(adds, removes);
+    for ((address, key), change) in &diff.storage {
+        let value = |value: &Option<Vec<u8>>| match value {
+            Some(value) => hexutil::to_hex(value),
+            None => "none".to_string(),
+        };
+        println!(
+            "    storage {:?} of {:?}: {} -> {}",
+            key,
+            address,
+            value(&change.old),
+            value(&change.new)
+        );
     }
 }
 
@@ -133,38 +277,74 @@ fn dump_block(root: &Path, block_num: usize, blockchain: &MockBlockchain) {
     let parent = blockchain.block(block_num - 1);
     let block = blockchain.block(block_num);
 
-    println!("block {}", block_num);
+    println!("block {}", block.header.number);
     println!("  hash: {:?}", block.hash());
+    println!("  timestamp: {}", block.header.timestamp);
     println!("  parent: {:?}", parent.hash());
-    println!("  state root: {:?}", block.state_root);
+    println!("  state root: {:?}", block.header.state_root);
+    println!("  deposits hash: {:?}", block.header.deposits_hash);
+    println!("  txns root: {:?}", block.header.txns_root);
+    println!("  receipts root: {:?}", block.header.receipts_root);
+    println!("  withdrawals root: {:?}", block.header.withdrawals_root);
+    print_diff(blockchain.diff(block_num));
     println!();
 
-    std::fs::create_dir_all(root.clone()).unwrap();
+    std::fs::create_dir_all(root).unwrap();
     std::fs::write(root.join("input"), block.hash()).unwrap();
-    std::fs::write(root.join("output"), block.state_root).unwrap();
+    std::fs::write(
+        root.join("output"),
+        output_root(
+            block.header.state_root,
+            block.header.receipts_root,
+            block.header.withdrawals_root,
+        ),
+    )
+    .unwrap();
 
-    // Serialize the current and parent block preimages
+    // Serialize the current and parent block header preimages
     std::fs::write(
         root.join(format!("0x{:?}", parent.hash())),
-        &parent.serialize(),
+        parent.header.serialize(),
     )
     .unwrap();
     std::fs::write(
         root.join(format!("0x{:?}", block.hash())),
-        &block.serialize(),
+        block.header.serialize(),
     )
     .unwrap();
-    std::fs::write(root.join("block"), &block.serialize()).unwrap();
+    // The challenge contract checks this against the block hash, so it must be the header.
+    std::fs::write(root.join("block"), block.header.serialize()).unwrap();
 
-    // Serialize the trie nodes
-    let nodes = blockchain.record_trie_nodes(block_num);
-    for (key, value) in nodes {
+    // Serialize the deposits, so that they can be fetched by their hash.
+    std::fs::write(
+        root.join(format!("0x{:?}", block.header.deposits_hash)),
+        rlp::encode_list(&block.deposits),
+    )
+    .unwrap();
+
+    // Serialize the transactions trie nodes, so that the transactions can be fetched one by one.
+    let (_, txns_nodes) = txns_trie(&block.txns);
+    for (key, value) in txns_nodes {
         std::fs::write(root.join(format!("0x{:?}", key)), &value).unwrap();
     }
+
+    // Serialize the state trie nodes needed to execute the block.
+    blockchain
+        .record_witness(block_num)
+        .write_preimages(root)
+        .unwrap();
 }
 
 fn main() {
-    let b = demo_blockchain();
+    // The genesis spec can be passed as a JSON file, see the `genesis` module for the format.
+    let genesis = match std::env::args_os().nth(1) {
+        Some(path) => genesis::load(Path::new(&path)).unwrap_or_else(|e| {
+            eprintln!("failed to load the genesis spec: {}", e);
+            std::process::exit(1);
+        }),
+        None => GenesisSpec::test(),
+    };
+    let b = demo_blockchain(&genesis);
     for i in 0..b.best_block_num() {
         // Cannon is a bit weird in how it identifies challenges.
         //
@@ -176,9 +356,8 @@ fn main() {
         //
         // Most importantly, that implies that here we need to put all the data required for
         // re-execution of the block N+1 into the directory for block N.
-        let last_good_block_num = i;
-        let challenged_block_num = i + 1;
+        let last_good_block_num = b.block(i).header.number;
         let root = PathBuf::from(format!("/tmp/cannon/0_{last_good_block_num}"));
-        dump_block(&root, challenged_block_num, &b);
+        dump_block(&root, i + 1, &b);
     }
 }
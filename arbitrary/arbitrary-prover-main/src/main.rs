@@ -15,7 +15,10 @@ mod heap;
 mod iommu;
 
 use alloc::boxed::Box;
-use arbitrary_state_machine::{Backend, Block, State, H256};
+use arbitrary_state_machine::{
+    output_root, validate_and_execute, Backend, Block, BlockError, Header, State, H256,
+    INVALID_BLOCK_ROOT,
+};
 
 use rlp::{Decodable, UntrustedRlp};
 
@@ -27,10 +30,29 @@ impl Backend for OracleBackend {
     }
 }
 
-/// Given the blockhash returns the block.
-fn lookup_block(hash: H256) -> Option<Block> {
-    let block_rlp = iommu::preimage(hash)?;
-    UntrustedRlp::new(&block_rlp).as_val().ok()
+/// Given the blockhash returns the block header.
+///
+/// Panics if the preimage of the header is not available, since in that case nothing can be
+/// proven. Returns an error if the preimage is not a valid header.
+fn lookup_header(hash: H256) -> Result<Header, BlockError> {
+    let header_rlp = iommu::preimage(hash).expect("header preimage is not available");
+    Ok(UntrustedRlp::new(&header_rlp).as_val()?)
+}
+
+/// Given the blockhash returns the block, fetching its deposits and transactions from the oracle.
+fn lookup_block(hash: H256) -> Result<Block, BlockError> {
+    Block::from_header(lookup_header(hash)?, &OracleBackend)
+}
+
+/// Commits to the invalidity of the input block and halts.
+///
+/// A missing trie node means that the preimages were not supplied, not that the block is invalid,
+/// so in that case there is nothing to commit to and we panic instead.
+fn output_invalid(err: BlockError) -> ! {
+    if let BlockError::MissingTrieNode(_) = err {
+        panic!("trie node is not available");
+    }
+    iommu::output(INVALID_BLOCK_ROOT)
 }
 
 /// Main entrypoint.
@@ -40,14 +62,27 @@ pub extern "C" fn _start() {
 
     let input_block = iommu::input_hash();
 
-    let block = lookup_block(input_block).unwrap();
-    let parent_block = lookup_block(block.parent).unwrap();
+    // A block that cannot be decoded or fails validation is committed to as `INVALID_BLOCK_ROOT`, so
+    // that the invalidity of the block can be proven instead of just crashing the VM.
+    let block = lookup_block(input_block).unwrap_or_else(|err| output_invalid(err));
+    // The parent block was accepted before, so it must be valid.
+    let parent_header = lookup_header(block.header.parent).unwrap();
 
-    let mut state = State::with_root(OracleBackend, parent_block.state_root);
-    arbitrary_state_machine::execute(&mut state, block);
-    let output = state.root();
+    let mut state = State::with_roots(
+        OracleBackend,
+        parent_header.state_root,
+        parent_header.withdrawals_root,
+    );
+    if let Err(err) = validate_and_execute(&mut state, &parent_header, &block) {
+        output_invalid(err);
+    }
 
-    iommu::output(output);
+    // The roots in the header were checked against the results of the execution.
+    iommu::output(output_root(
+        block.header.state_root,
+        block.header.receipts_root,
+        block.header.withdrawals_root,
+    ));
 }
 
 #[panic_handler]
@@ -0,0 +1,122 @@
+//! Compares two ways of committing a block's worth of account writes to the account trie: key by
+//! key with `trie::insert`, which is how `State::commit` used to do it, and through a
+//! `trie::TrieCache`, which is what it does now.
+//!
+//! Run with `cargo bench --bench commit_hashing`.
+//!
+//! This runs on the host and does not measure the MIPS guest. It reports the node hashes and keccak-f
+//! permutations the trie crate actually computes, counted with its `hash-count` feature, since
+//! keccak dominates the cost of a commit in the guest. The host time is only given for reference.
+//! It is not a step count: that requires running both commit paths in the guest under `mipsevm`,
+//! which this bench doesn't do.
+
+use arbitrary_state_machine::{keccak256, Account, H256, U256};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use trie::{hash_count, HashCount, TrieCache};
+
+/// The number of accounts in the state.
+const ACCOUNTS: u64 = 10_000;
+
+/// The numbers of transfers in a block. Every transfer writes two accounts.
+const TRANSFERS: [u64; 4] = [1, 10, 100, 1000];
+
+fn account_key(index: u64) -> Vec<u8> {
+    keccak256(&index.to_be_bytes()).0.to_vec()
+}
+
+fn account(nonce: u64) -> Vec<u8> {
+    rlp::encode(&Account {
+        nonce,
+        balance: U256::from(1_000_000_000u64),
+        ..Account::default()
+    })
+}
+
+/// The writes of a block with the given number of transfers between pseudo-random accounts.
+fn block_writes(transfers: u64) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut seed = transfers;
+    let mut next = || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % ACCOUNTS
+    };
+    (0..transfers)
+        .flat_map(|_| [next(), next()])
+        .map(|index| (account_key(index), account(1)))
+        .collect()
+}
+
+fn sequential(
+    root: H256,
+    database: &mut BTreeMap<H256, Vec<u8>>,
+    writes: &BTreeMap<Vec<u8>, Vec<u8>>,
+) -> H256 {
+    let mut root = root;
+    for (key, value) in writes {
+        let (new_root, change) = trie::insert(root, &&*database, key, value).unwrap();
+        database.extend(change.adds);
+        root = new_root;
+    }
+    root
+}
+
+fn cached(
+    root: H256,
+    database: &BTreeMap<H256, Vec<u8>>,
+    writes: &BTreeMap<Vec<u8>, Vec<u8>>,
+) -> H256 {
+    let mut cache = TrieCache::new(root);
+    for (key, value) in writes {
+        cache.insert(&database, key, value).unwrap();
+    }
+    cache.commit().0
+}
+
+/// Runs `f`, returning its result with the node hashes it computed and the time it took.
+fn measure<F: FnMut() -> H256>(mut f: F) -> (H256, HashCount, Duration) {
+    let count = hash_count();
+    let start = Instant::now();
+    let root = f();
+    let elapsed = start.elapsed();
+    (root, hash_count() - count, elapsed)
+}
+
+fn main() {
+    let accounts = (0..ACCOUNTS)
+        .map(|index| (account_key(index), account(0)))
+        .collect();
+    let (root, change) = trie::build(&accounts);
+    let database = change.adds;
+
+    println!(
+        "{:>9} {:>7} | {:>14} {:>14} {:>12} | {:>14} {:>14} {:>12}",
+        "transfers",
+        "writes",
+        "seq. hashes",
+        "seq. keccak-f",
+        "seq. time",
+        "cache hashes",
+        "cache keccak-f",
+        "cache time",
+    );
+    for transfers in TRANSFERS {
+        let writes = block_writes(transfers);
+        let mut copy = database.clone();
+        let (expected, old, old_time) = measure(|| sequential(root, &mut copy, &writes));
+        let (actual, new, new_time) = measure(|| cached(root, &database, &writes));
+        assert_eq!(actual, expected);
+        println!(
+            "{:>9} {:>7} | {:>14} {:>14} {:>12.2?} | {:>14} {:>14} {:>12.2?}",
+            transfers,
+            writes.len(),
+            old.hashes,
+            old.permutations,
+            old_time,
+            new.hashes,
+            new.permutations,
+            new_time,
+        );
+    }
+}
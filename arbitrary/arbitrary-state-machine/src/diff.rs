@@ -0,0 +1,47 @@
+//! Structured diffs of the state, see [`crate::execute_with_diff`].
+
+use crate::state::Account;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bigint::{H256, U256};
+
+/// The value of something before and after a change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diff<T> {
+    pub old: T,
+    pub new: T,
+}
+
+/// The changes made to the state by executing a block.
+///
+/// Only the entries that actually changed are listed: an account that was modified and then
+/// restored is not. Withdrawals are not listed since they can't change once made, the receipts
+/// have them.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct StateDiff {
+    /// The changed accounts by address. `None` stands for an absent account, so an account is
+    /// created if the old value is `None` and deleted if the new one is.
+    pub accounts: BTreeMap<H256, Diff<Option<Account>>>,
+    /// The changed balances of assets other than the native one, by address and asset.
+    pub assets: BTreeMap<(H256, H256), Diff<U256>>,
+    /// The changed storage entries, by address and key. `None` stands for an absent entry.
+    pub storage: BTreeMap<(H256, H256), Diff<Option<Vec<u8>>>>,
+}
+
+impl StateDiff {
+    /// Returns the addresses of the accounts that were created.
+    pub fn created(&self) -> impl Iterator<Item = H256> + '_ {
+        self.accounts
+            .iter()
+            .filter(|(_, diff)| diff.old.is_none())
+            .map(|(address, _)| *address)
+    }
+
+    /// Returns the addresses of the accounts that were deleted.
+    pub fn deleted(&self) -> impl Iterator<Item = H256> + '_ {
+        self.accounts
+            .iter()
+            .filter(|(_, diff)| diff.new.is_none())
+            .map(|(address, _)| *address)
+    }
+}
@@ -0,0 +1,99 @@
+//! Errors that can be produced by the state transition function.
+
+use bigint::H256;
+
+/// A trie node that is not available in the backend of the state, by hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MissingNode(pub H256);
+
+impl From<trie::Error> for MissingNode {
+    fn from(e: trie::Error) -> Self {
+        match e {
+            trie::Error::Require(hash) => MissingNode(hash),
+        }
+    }
+}
+
+/// An error that can occur when applying a transaction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TxnError {
+    /// The transaction is meant for a different chain.
+    InvalidChainId,
+    /// The signature is malformed or was not produced by the sender.
+    InvalidSignature,
+    /// The nonce of the transaction does not match the nonce of the sender account.
+    InvalidNonce,
+    /// The lock of the transaction is not open for the block yet.
+    Locked,
+    /// The sender does not have enough funds to pay the fee.
+    InsufficientFunds,
+    /// The value to store is longer than [`crate::MAX_STORAGE_VALUE_LEN`].
+    ValueTooLarge,
+    /// Crediting the value would overflow the balance of the recipient.
+    BalanceOverflow,
+}
+
+/// An error that can occur when executing a block.
+///
+/// The policy is to reject the whole block if any of its transactions is invalid. There is no
+/// partial execution: [`crate::execute`] reverts the changes made by a rejected block, so the state
+/// can be used again as if the block had never been executed. The exception is
+/// [`BlockError::MissingTrieNode`], after which the state must be discarded.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BlockError {
+    /// The transaction at the given index within the block is invalid.
+    InvalidTxn { index: usize, error: TxnError },
+    /// The chain id of the block does not match the chain id of the parent block.
+    InvalidChainId,
+    /// The gas limit of the block does not match the gas limit of the parent block.
+    InvalidGasLimit,
+    /// The block number is not the successor of the parent block number.
+    InvalidNumber,
+    /// The timestamp of the block is not greater than the timestamp of the parent block.
+    InvalidTimestamp,
+    /// The parent hash of the block does not match the hash of the parent header.
+    InvalidParentHash,
+    /// The transactions root of the header is not the root of a trie holding the transactions under
+    /// consecutive indices and nothing else.
+    TxnsRootMismatch,
+    /// The state root computed by the execution does not match the one in the header.
+    StateRootMismatch,
+    /// The receipts root computed by the execution does not match the one in the header.
+    ReceiptsRootMismatch,
+    /// The withdrawals root computed by the execution does not match the one in the header.
+    WithdrawalsRootMismatch,
+    /// The transaction at the given index could use more gas than what is left of the gas limit of
+    /// the block, see [`crate::max_txn_gas`].
+    GasLimitExceeded { index: usize },
+    /// The deposits of the block use more gas than the gas limit of the block.
+    DepositsGasLimitExceeded,
+    /// Crediting the fees would overflow the balance of the beneficiary.
+    FeeOverflow,
+    /// Crediting the deposit at the given index would overflow the balance of the recipient.
+    DepositOverflow { index: usize },
+    /// The block could not be decoded.
+    Decode(rlp::DecoderError),
+    /// A trie node or another preimage required for the execution is not available in the
+    /// backend.
+    MissingTrieNode(H256),
+}
+
+impl From<rlp::DecoderError> for BlockError {
+    fn from(e: rlp::DecoderError) -> Self {
+        BlockError::Decode(e)
+    }
+}
+
+impl From<trie::Error> for BlockError {
+    fn from(e: trie::Error) -> Self {
+        match e {
+            trie::Error::Require(hash) => BlockError::MissingTrieNode(hash),
+        }
+    }
+}
+
+impl From<MissingNode> for BlockError {
+    fn from(MissingNode(hash): MissingNode) -> Self {
+        BlockError::MissingTrieNode(hash)
+    }
+}
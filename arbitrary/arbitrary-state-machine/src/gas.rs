@@ -0,0 +1,66 @@
+//! The cost model of transactions.
+//!
+//! Every block has a gas limit, so that the execution of a block, and hence the bisection game over
+//! it, is bounded. A transaction costs a base amount, which covers decoding it and recovering the
+//! signature, plus an amount per trie read and write it makes. Trie accesses dominate the rest of
+//! the execution since every node on the path has to be fetched from the preimage oracle and
+//! hashed.
+//!
+//! The gas of a transaction is only known once it has run, so a block also checks an upper bound of
+//! it, [`max_txn_gas`], before running it. That way a block never executes more than its gas limit
+//! allows, however the transactions are crafted. Deposits are charged against the gas limit too,
+//! see [`deposits_gas`], even though they are paid for on L1. The same bounds are already checked
+//! while fetching the block, see [`crate::Block::from_header`], so that the guest doesn't fetch
+//! and decode more than the gas limit allows either.
+
+use crate::primitives::TxnEnvelope;
+use crate::state::TrieAccesses;
+use bigint::Gas;
+
+/// The gas paid by every transaction, whatever it does.
+pub const TXN_BASE_GAS: u64 = 21_000;
+
+/// The gas paid per read of a trie.
+pub const TRIE_READ_GAS: u64 = 2_100;
+
+/// The gas paid per write to a trie, including removals. A write is more expensive than a read
+/// since the path has to be rehashed.
+pub const TRIE_WRITE_GAS: u64 = 5_000;
+
+/// Returns the gas used by a transaction that made the given trie accesses.
+pub fn txn_gas(accesses: TrieAccesses) -> Gas {
+    Gas::from(TXN_BASE_GAS)
+        + Gas::from(accesses.reads) * Gas::from(TRIE_READ_GAS)
+        + Gas::from(accesses.writes) * Gas::from(TRIE_WRITE_GAS)
+}
+
+/// Returns the most gas the given transaction can use, which only depends on its shape.
+pub fn max_txn_gas(txn: &TxnEnvelope) -> Gas {
+    // Charging the sender reads and writes its account.
+    let mut accesses = TrieAccesses {
+        reads: 1,
+        writes: 1,
+    };
+    let outputs = match txn {
+        TxnEnvelope::Transfer(_) => 1,
+        TxnEnvelope::Batch(txn) => txn.outputs.len() as u64,
+        TxnEnvelope::Store(_) => {
+            // The account is read and written along with the value.
+            accesses.reads += 1;
+            accesses.writes += 2;
+            return txn_gas(accesses);
+        }
+    };
+    // At worst, the asset is not the native one: looking up the admin and the balance of the sender
+    // takes two reads each, since both are in a storage trie, and debiting the sender one read and
+    // two writes. Crediting an output does the same as looking up and debiting.
+    accesses.reads += 5 + 3 * outputs;
+    accesses.writes += 2 + 2 * outputs;
+    txn_gas(accesses)
+}
+
+/// Returns the gas used by minting the given number of deposits, which read and write the account
+/// of their recipient.
+pub fn deposits_gas(deposits: usize) -> Gas {
+    Gas::from(deposits as u64) * Gas::from(TRIE_READ_GAS + TRIE_WRITE_GAS)
+}
@@ -0,0 +1,165 @@
+//! The specification of the genesis block and state.
+
+use crate::primitives::{deposits_hash, Block, Header};
+use crate::state::{admin_key, Account, Backend, InMemoryBackend, State};
+use crate::{ALICE, BOB, CHARLIE, DAVE, EVE, TEST_CHAIN_ID, TEST_GAS_LIMIT};
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use bigint::{Gas, H256, U256};
+
+/// Describes the genesis of a chain.
+///
+/// Encoded as an RLP list `[chain_id, number, timestamp, gas_limit, extra_data, admin, [[address,
+/// balance], ...]]` with the allocations sorted by address.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct GenesisSpec {
+    /// Identifies the chain. Put into the genesis header and inherited by every block.
+    pub chain_id: u64,
+    /// The number of the genesis block. The first block built on top of it has the next number.
+    pub number: u64,
+    /// The timestamp of the genesis block. Every block must have a greater timestamp than its
+    /// parent.
+    pub timestamp: u64,
+    /// The gas limit of the genesis header, inherited by every block.
+    pub gas_limit: Gas,
+    /// Arbitrary data put into the genesis header.
+    pub extra_data: Vec<u8>,
+    /// The account that can create and mint assets other than the native one. The zero address
+    /// means that there is no admin, so there is only the native asset.
+    pub admin: H256,
+    /// The initial balances of the native asset. All accounts start with a zero nonce, so the ones
+    /// with a zero balance are empty and are not created.
+    pub alloc: BTreeMap<H256, U256>,
+}
+
+impl rlp::Encodable for GenesisSpec {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        s.begin_list(7);
+        s.append(&self.chain_id);
+        s.append(&self.number);
+        s.append(&self.timestamp);
+        s.append(&self.gas_limit);
+        s.append(&self.extra_data);
+        s.append(&self.admin);
+        s.begin_list(self.alloc.len());
+        for (address, balance) in &self.alloc {
+            s.begin_list(2);
+            s.append(address);
+            s.append(balance);
+        }
+    }
+}
+
+impl rlp::Decodable for GenesisSpec {
+    fn decode(rlp: &rlp::UntrustedRlp) -> Result<Self, rlp::DecoderError> {
+        // Insist on the canonical form, so that a spec has exactly one encoding.
+        if rlp.item_count()? != 7 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+        let mut alloc = BTreeMap::new();
+        for entry in rlp.at(6)?.iter() {
+            if entry.item_count()? != 2 {
+                return Err(rlp::DecoderError::RlpIncorrectListLen);
+            }
+            let address: H256 = entry.val_at(0)?;
+            if alloc
+                .keys()
+                .next_back()
+                .is_some_and(|last| *last >= address)
+            {
+                return Err(rlp::DecoderError::Custom("allocations are not sorted"));
+            }
+            alloc.insert(address, entry.val_at(1)?);
+        }
+        Ok(GenesisSpec {
+            chain_id: rlp.val_at(0)?,
+            number: rlp.val_at(1)?,
+            timestamp: rlp.val_at(2)?,
+            gas_limit: rlp.val_at(3)?,
+            extra_data: rlp.val_at(4)?,
+            admin: rlp.val_at(5)?,
+            alloc,
+        })
+    }
+}
+
+impl GenesisSpec {
+    /// The genesis of the test chain that funds the well-known test accounts.
+    pub fn test() -> Self {
+        GenesisSpec {
+            chain_id: TEST_CHAIN_ID,
+            number: 0,
+            timestamp: 0,
+            gas_limit: TEST_GAS_LIMIT.into(),
+            extra_data: Vec::new(),
+            admin: DAVE,
+            alloc: [
+                (ALICE, 100),
+                (BOB, 90),
+                (CHARLIE, 80),
+                (DAVE, 70),
+                (EVE, 60),
+            ]
+            .into_iter()
+            .map(|(address, balance)| (address, U256::from(balance)))
+            .collect(),
+        }
+    }
+
+    /// Creates the genesis block and state described by this spec.
+    ///
+    /// The state trie is built in one go rather than by inserting the accounts one by one, so this
+    /// is cheap even for large allocations.
+    pub fn build(&self) -> (Block, State<InMemoryBackend>) {
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = self
+            .alloc
+            .iter()
+            .filter(|(_, balance)| !balance.is_zero())
+            .map(|(address, balance)| {
+                let account = Account {
+                    nonce: 0,
+                    balance: *balance,
+                    ..Default::default()
+                };
+                (address.0.to_vec(), rlp::encode(&account))
+            })
+            .collect();
+        let mut backend = InMemoryBackend::new();
+        if !self.admin.is_zero() {
+            // The admin is kept in the storage of a system account, see `State::admin`.
+            let storage = BTreeMap::from([(admin_key().0.to_vec(), rlp::encode(&self.admin))]);
+            let (storage_root, change) = trie::build(&storage);
+            backend.apply_changes(change.adds, change.removes);
+            let account = Account {
+                nonce: 0,
+                balance: self.alloc.get(&admin_key()).copied().unwrap_or_default(),
+                storage_root,
+            };
+            entries.insert(admin_key().0.to_vec(), rlp::encode(&account));
+        }
+        let (root, change) = trie::build(&entries);
+        backend.apply_changes(change.adds, change.removes);
+        let state = State::with_roots(backend, root, trie::EMPTY_TRIE_HASH);
+
+        let block = Block {
+            header: Header {
+                chain_id: self.chain_id,
+                number: self.number,
+                timestamp: self.timestamp,
+                parent: H256::zero(),
+                state_root: root,
+                beneficiary: H256::zero(),
+                gas_limit: self.gas_limit,
+                deposits_hash: deposits_hash(&[]),
+                txns_root: trie::EMPTY_TRIE_HASH,
+                receipts_root: trie::EMPTY_TRIE_HASH,
+                withdrawals_root: trie::EMPTY_TRIE_HASH,
+                extra_data: self.extra_data.clone(),
+            },
+            deposits: Vec::new(),
+            txns: Vec::new(),
+        };
+
+        (block, state)
+    }
+}
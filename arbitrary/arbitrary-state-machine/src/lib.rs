@@ -1,93 +1,1705 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
+mod diff;
+mod error;
+mod gas;
+mod genesis;
 mod primitives;
+mod signature;
 mod state;
+mod witness;
 
 use alloc::vec::Vec;
 
-pub use bigint::{H256, U256};
+pub use bigint::{Gas, H256, U256};
+pub use diff::{Diff, StateDiff};
+pub use error::{BlockError, MissingNode, TxnError};
+pub use gas::{deposits_gas, max_txn_gas, txn_gas, TRIE_READ_GAS, TRIE_WRITE_GAS, TXN_BASE_GAS};
+pub use genesis::GenesisSpec;
 pub use primitives::*;
-pub use state::{Backend, InMemoryBackend, State};
+pub use signature::{address, Signature};
+pub use state::{asset_key, Account, Backend, Checkpoint, InMemoryBackend, State, TrieAccesses};
+pub use trie::EMPTY_TRIE_HASH;
+#[cfg(feature = "std")]
+pub use witness::RecordingBackend;
+pub use witness::{Witness, WitnessBackend};
 
-pub const ALICE: H256 = H256([0x01; 32]);
-pub const BOB: H256 = H256([0x02; 32]);
-pub const CHARLIE: H256 = H256([0x03; 32]);
-pub const DAVE: H256 = H256([0x04; 32]);
-pub const EVE: H256 = H256([0x05; 32]);
+/// The chain id of the test chain, see [`GenesisSpec::test`].
+pub const TEST_CHAIN_ID: u64 = 1337;
 
-pub fn execute(state: &mut State<impl Backend>, block: Block) {
-    for txn in &block.txns {
-        apply_txn(state, txn).unwrap();
+/// The gas limit of the test chain, see [`GenesisSpec::test`]. Fits about twenty transfers.
+pub const TEST_GAS_LIMIT: u64 = 1_000_000;
+
+// Well-known test accounts. The secret keys are public and must only be used for testing.
+pub const ALICE_SECRET: [u8; 32] = [0x01; 32];
+pub const BOB_SECRET: [u8; 32] = [0x02; 32];
+pub const CHARLIE_SECRET: [u8; 32] = [0x03; 32];
+pub const DAVE_SECRET: [u8; 32] = [0x04; 32];
+pub const EVE_SECRET: [u8; 32] = [0x05; 32];
+
+// The addresses of the test accounts, i.e. `address(&ALICE_SECRET)` and so on.
+pub const ALICE: H256 = H256([
+    0xb8, 0xa0, 0x72, 0x2a, 0xe6, 0xcb, 0x48, 0xcd, 0xe0, 0xb4, 0xae, 0x1f, 0x1a, 0x64, 0x2f, 0x0e,
+    0x3c, 0x3a, 0xf5, 0x45, 0xe7, 0xac, 0xbd, 0x38, 0xb0, 0x72, 0x51, 0xb3, 0x99, 0x09, 0x14, 0xf1,
+]);
+pub const BOB: H256 = H256([
+    0xa9, 0x59, 0x05, 0xf8, 0xda, 0xb9, 0xc2, 0x77, 0x71, 0x5d, 0x6f, 0xd0, 0x50, 0x50, 0xa4, 0xf4,
+    0xb3, 0xf9, 0x33, 0x8c, 0x34, 0x72, 0xdc, 0xc0, 0x1a, 0x87, 0xc7, 0x6a, 0x14, 0x4b, 0x3c, 0x9c,
+]);
+pub const CHARLIE: H256 = H256([
+    0x30, 0x56, 0x81, 0xa4, 0xdc, 0x83, 0x0a, 0x58, 0xa8, 0x4c, 0x7c, 0xdf, 0x33, 0x25, 0xa7, 0x84,
+    0x25, 0xf1, 0x7a, 0x7e, 0x48, 0x7e, 0xb5, 0x66, 0x6b, 0x2b, 0xfd, 0x93, 0xab, 0xb0, 0x6c, 0x70,
+]);
+pub const DAVE: H256 = H256([
+    0xa1, 0x51, 0xbc, 0x9b, 0x5e, 0xa3, 0x0f, 0xe3, 0x92, 0x2a, 0x90, 0x31, 0xc4, 0x8b, 0x81, 0x2b,
+    0xb4, 0x34, 0x01, 0x39, 0x2c, 0x03, 0x73, 0x81, 0xac, 0xa9, 0x34, 0xf4, 0x06, 0x9c, 0x05, 0x17,
+]);
+pub const EVE: H256 = H256([
+    0x0a, 0xc0, 0xcd, 0x74, 0xa4, 0x4c, 0x6b, 0xb4, 0xff, 0x0a, 0x67, 0x1e, 0xd0, 0x9a, 0xd1, 0x40,
+    0x80, 0xd4, 0xb2, 0x57, 0xa8, 0x19, 0xa4, 0xf5, 0x79, 0xb8, 0x48, 0x5b, 0xe8, 0x8f, 0x08, 0x6c,
+]);
+
+/// The output committed by the prover for a block that was rejected by [`execute`].
+///
+/// No output root can have this value since that would require knowing a preimage of the zero
+/// hash.
+pub const INVALID_BLOCK_ROOT: H256 = H256([0x00; 32]);
+
+/// Returns the output committed by the prover for an accepted block. It commits to the state root,
+/// the receipts root and the withdrawals root, so that receipts and withdrawals can be proven
+/// against it.
+pub fn output_root(state_root: H256, receipts_root: H256, withdrawals_root: H256) -> H256 {
+    let mut preimage = [0; 96];
+    preimage[..32].copy_from_slice(&state_root.0);
+    preimage[32..64].copy_from_slice(&receipts_root.0);
+    preimage[64..].copy_from_slice(&withdrawals_root.0);
+    keccak256(&preimage)
+}
+
+/// Execute all transactions of the block on top of the given state and credit the collected fees
+/// to the beneficiary of the block. Returns the receipts of the transactions.
+///
+/// The deposits of the block are minted before any of the transactions is applied, so the
+/// transactions can already spend them. Deposits do not produce receipts.
+///
+/// If any of the transactions is invalid, including carrying a chain id other than the one of the
+/// block, the whole block is rejected and the error is returned. So is a block whose deposits or
+/// transactions could use more gas than its gas limit. In that case the changes made by the block
+/// are reverted. Note that a failed transaction is not invalid, it just has an unsuccessful receipt.
+///
+/// Otherwise the changes are committed, see [`State::commit`], so the roots of the state are the
+/// ones after the block.
+///
+/// If a trie node needed by the execution is missing from the backend, whatever the outcome of the
+/// block, [`BlockError::MissingTrieNode`] is returned with the first missing node and the state must
+/// be discarded, see [`State::missing_node`].
+///
+/// The deposits and the transactions are metered against the gas limit of the block before they
+/// are run, see [`max_txn_gas`].
+pub fn execute(state: &mut State<impl Backend>, block: &Block) -> Result<Vec<Receipt>, BlockError> {
+    let checkpoint = state.checkpoint();
+    let receipts = apply_block(state, block);
+    match receipts {
+        Ok(_) => state.commit(),
+        Err(_) => state.revert_to(checkpoint),
+    }
+    // The outcome of the block can't be trusted if a part of the state was missing.
+    if let Some(hash) = state.missing_node() {
+        return Err(BlockError::MissingTrieNode(hash));
+    }
+    receipts
+}
+
+/// Applies the deposits, the transactions and the fees of the block without committing them. See
+/// [`execute`].
+fn apply_block(state: &mut State<impl Backend>, block: &Block) -> Result<Vec<Receipt>, BlockError> {
+    let deposits_gas = deposits_gas(block.deposits.len());
+    if deposits_gas > block.header.gas_limit {
+        return Err(BlockError::DepositsGasLimitExceeded);
+    }
+    for (index, deposit) in block.deposits.iter().enumerate() {
+        let mut account = state
+            .lenient(state.get_account(deposit.to))
+            .unwrap_or_default();
+        account.balance = checked_add(account.balance, deposit.value)
+            .ok_or(BlockError::DepositOverflow { index })?;
+        state.put_account(deposit.to, &account);
+    }
+
+    let mut fees: u64 = 0;
+    let mut gas_used = Gas::zero();
+    let mut receipts = Vec::with_capacity(block.txns.len());
+    for (index, txn) in block.txns.iter().enumerate() {
+        if txn.chain_id() != block.header.chain_id {
+            return Err(BlockError::InvalidTxn {
+                index,
+                error: TxnError::InvalidChainId,
+            });
+        }
+        let max_gas = max_txn_gas(txn);
+        if deposits_gas + gas_used + max_gas > block.header.gas_limit {
+            return Err(BlockError::GasLimitExceeded { index });
+        }
+        let receipt = match txn {
+            TxnEnvelope::Transfer(txn) => apply_txn(state, &block.header, txn),
+            TxnEnvelope::Batch(txn) => apply_batch_txn(state, txn),
+            TxnEnvelope::Store(txn) => apply_store_txn(state, txn),
+        };
+        let mut receipt = receipt.map_err(|error| BlockError::InvalidTxn { index, error })?;
+        fees = fees
+            .checked_add(receipt.fee)
+            .ok_or(BlockError::FeeOverflow)?;
+        receipt.cumulative_fees = fees;
+        debug_assert!(receipt.gas_used <= max_gas);
+        gas_used = gas_used + receipt.gas_used;
+        receipt.cumulative_gas_used = gas_used;
+        receipts.push(receipt);
+    }
+    // Don't touch the beneficiary if there is nothing to credit, so that a block without fees does
+    // not create an empty account.
+    if fees > 0 {
+        let mut beneficiary = state
+            .lenient(state.get_account(block.header.beneficiary))
+            .unwrap_or_default();
+        beneficiary.balance =
+            checked_add(beneficiary.balance, U256::from(fees)).ok_or(BlockError::FeeOverflow)?;
+        state.set_account(block.header.beneficiary, &beneficiary);
     }
+    Ok(receipts)
 }
 
-/// An error that can occur when applying a transaction.
-#[derive(Debug)]
-pub struct InsufficientFunds;
+/// Like [`execute`], but also returns the changes made to the state by the block, including the
+/// deposits and the fees. The receipts are the same as the ones returned by [`execute`].
+pub fn execute_with_diff(
+    state: &mut State<impl Backend>,
+    block: &Block,
+) -> Result<(Vec<Receipt>, StateDiff), BlockError> {
+    state.record_diff();
+    let receipts = execute(state, block);
+    let diff = state.take_diff();
+    Ok((receipts?, diff))
+}
+
+/// Validates the block against its parent and executes it. Returns the receipts of the
+/// transactions.
+///
+/// The state must be at the state and withdrawals roots of the parent. On top of the checks done
+/// by [`execute`], this checks that the block is the direct successor of the parent on the same
+/// chain with the same gas limit and a later timestamp, and that the state, receipts and
+/// withdrawals roots in the header match the result of the execution. The deposits and the
+/// transactions are assumed to match the deposits hash and the transactions root, which
+/// [`Block::from_header`] checks.
+pub fn validate_and_execute(
+    state: &mut State<impl Backend>,
+    parent: &Header,
+    block: &Block,
+) -> Result<Vec<Receipt>, BlockError> {
+    if block.header.chain_id != parent.chain_id {
+        return Err(BlockError::InvalidChainId);
+    }
+    if block.header.gas_limit != parent.gas_limit {
+        return Err(BlockError::InvalidGasLimit);
+    }
+    if parent.number.checked_add(1) != Some(block.header.number) {
+        return Err(BlockError::InvalidNumber);
+    }
+    if block.header.timestamp <= parent.timestamp {
+        return Err(BlockError::InvalidTimestamp);
+    }
+    if block.header.parent != parent.hash() {
+        return Err(BlockError::InvalidParentHash);
+    }
+    let receipts = execute(state, block)?;
+    if state.root() != block.header.state_root {
+        return Err(BlockError::StateRootMismatch);
+    }
+    if receipts_trie(&receipts).0 != block.header.receipts_root {
+        return Err(BlockError::ReceiptsRootMismatch);
+    }
+    if state.withdrawals_root() != block.header.withdrawals_root {
+        return Err(BlockError::WithdrawalsRootMismatch);
+    }
+    Ok(receipts)
+}
+
+/// Apply a transaction included in the block with the given header to the state. Returns the
+/// receipt of the transaction or an error if the transaction is invalid.
+///
+/// A transaction is invalid if it has a bad signature or nonce, if it has a lock that is not open
+/// for the block yet, or if the sender cannot pay the fee.
+/// If the sender can pay the fee but not the value, the transaction fails: the fee is paid and the
+/// nonce is bumped, but nothing is transferred.
+///
+/// The fee is always paid in the native asset, whatever asset is transferred. If the sender is the
+/// admin and the asset is not the native one, the value is minted rather than taken from the
+/// sender.
+///
+/// The fee is deducted from the sender, but it is not credited to anyone. That is done by
+/// [`execute`] once per block. The `cumulative_fees` and `cumulative_gas_used` of the returned
+/// receipt are set to the fee and the gas of this transaction alone. The gas is charged for the
+/// trie accesses made by the transaction, see [`txn_gas`].
+///
+/// A transfer to [`WITHDRAWALS_ADDRESS`] is a withdrawal: the value is not credited to anyone but
+/// recorded in the withdrawals trie instead.
+///
+/// Accounts that end up empty are removed from the state, see [`State::put_account`].
+pub fn apply_txn(
+    state: &mut State<impl Backend>,
+    header: &Header,
+    txn: &Txn,
+) -> Result<Receipt, TxnError> {
+    if txn.signature.recover(txn.signing_hash()) != Some(txn.from) {
+        return Err(TxnError::InvalidSignature);
+    }
+    if txn.lock.is_some_and(|lock| !lock.is_open(header)) {
+        return Err(TxnError::Locked);
+    }
+    let accesses = state.trie_accesses();
+    charge_sender(state, txn.from, txn.nonce, txn.fee)?;
+    let outputs = [Output {
+        to: txn.to,
+        value: txn.value,
+    }];
+    let (success, logs) = pay(state, txn.from, txn.asset, &outputs)?;
+    let gas_used = txn_gas(state.trie_accesses().since(accesses));
+    Ok(Receipt {
+        success,
+        fee: txn.fee,
+        cumulative_fees: txn.fee,
+        gas_used,
+        cumulative_gas_used: gas_used,
+        logs,
+    })
+}
 
-/// Apply a transaction to the state. Returns an error if the transaction is invalid.
-pub fn apply_txn(state: &mut State<impl Backend>, txn: &Txn) -> Result<(), InsufficientFunds> {
-    let source = state.get(txn.from).unwrap_or_default();
-    if source < txn.value {
-        return Err(InsufficientFunds);
+/// Apply a batch transaction to the state. Returns the receipt of the transaction or an error if
+/// the transaction is invalid.
+///
+/// Works like [`apply_txn`], except that there are many outputs. If the sender cannot afford all of
+/// them, the transaction fails and none is paid.
+pub fn apply_batch_txn(
+    state: &mut State<impl Backend>,
+    txn: &BatchTxn,
+) -> Result<Receipt, TxnError> {
+    if txn.signature.recover(txn.signing_hash()) != Some(txn.from) {
+        return Err(TxnError::InvalidSignature);
     }
-    let dest = state.get(txn.to).unwrap_or_default();
-    state.set(txn.from, source - txn.value);
-    state.set(txn.to, dest + txn.value);
+    let accesses = state.trie_accesses();
+    charge_sender(state, txn.from, txn.nonce, txn.fee)?;
+    let (success, logs) = pay(state, txn.from, txn.asset, &txn.outputs)?;
+    let gas_used = txn_gas(state.trie_accesses().since(accesses));
+    Ok(Receipt {
+        success,
+        fee: txn.fee,
+        cumulative_fees: txn.fee,
+        gas_used,
+        cumulative_gas_used: gas_used,
+        logs,
+    })
+}
+
+/// Apply a storage write to the state. Returns the receipt of the transaction or an error if the
+/// transaction is invalid.
+///
+/// Validated like [`apply_txn`]. On top of that, the value must not be longer than
+/// [`MAX_STORAGE_VALUE_LEN`]. A valid storage write always succeeds.
+pub fn apply_store_txn(
+    state: &mut State<impl Backend>,
+    txn: &StoreTxn,
+) -> Result<Receipt, TxnError> {
+    if txn.signature.recover(txn.signing_hash()) != Some(txn.from) {
+        return Err(TxnError::InvalidSignature);
+    }
+    if txn.value.len() > MAX_STORAGE_VALUE_LEN {
+        return Err(TxnError::ValueTooLarge);
+    }
+    let accesses = state.trie_accesses();
+    charge_sender(state, txn.from, txn.nonce, txn.fee)?;
+    state.storage_set(txn.from, txn.key, &txn.value);
+    let gas_used = txn_gas(state.trie_accesses().since(accesses));
+    Ok(Receipt {
+        success: true,
+        fee: txn.fee,
+        cumulative_fees: txn.fee,
+        gas_used,
+        cumulative_gas_used: gas_used,
+        logs: Vec::new(),
+    })
+}
+
+/// Checks the nonce of the sender, bumps it and deducts the fee.
+fn charge_sender(
+    state: &mut State<impl Backend>,
+    from: H256,
+    nonce: u64,
+    fee: u64,
+) -> Result<(), TxnError> {
+    let mut source = state.lenient(state.get_account(from)).unwrap_or_default();
+    if nonce != source.nonce {
+        return Err(TxnError::InvalidNonce);
+    }
+    let fee = U256::from(fee);
+    if source.balance < fee {
+        return Err(TxnError::InsufficientFunds);
+    }
+    source.nonce += 1;
+    source.balance = source.balance - fee;
+    state.put_account(from, &source);
     Ok(())
 }
 
-/// Creates the genesis state with filled balances for ALICE and BOB.
+/// Pays the outputs from the sender, all or nothing. Returns whether the payment succeeded and the
+/// logs it emitted. If an output can't be credited, the payments made so far are reverted.
+fn pay(
+    state: &mut State<impl Backend>,
+    from: H256,
+    asset: H256,
+    outputs: &[Output],
+) -> Result<(bool, Vec<Log>), TxnError> {
+    let checkpoint = state.checkpoint();
+    let result = pay_outputs(state, from, asset, outputs);
+    if result.is_err() {
+        state.revert_to(checkpoint);
+    }
+    result
+}
+
+fn pay_outputs(
+    state: &mut State<impl Backend>,
+    from: H256,
+    asset: H256,
+    outputs: &[Output],
+) -> Result<(bool, Vec<Log>), TxnError> {
+    let mint = asset != NATIVE_ASSET && state.lenient(state.admin()) == Some(from);
+    let available = state.lenient(state.balance(from, asset));
+    let total = outputs.iter().try_fold(U256::zero(), |total, output| {
+        checked_add(total, output.value)
+    });
+    let success = mint || total.is_some_and(|total| available >= total);
+    if !success {
+        return Ok((false, Vec::new()));
+    }
+    if !mint {
+        // Can't overflow since it's not more than the available balance.
+        state.set_balance(from, asset, available - total.unwrap_or_default());
+    }
+
+    let mut logs = Vec::new();
+    for output in outputs {
+        if output.to == WITHDRAWALS_ADDRESS {
+            let withdrawal = Withdrawal {
+                from,
+                asset,
+                value: output.value,
+            };
+            let index = state.push_withdrawal(&withdrawal);
+            logs.push(Log::withdrawal(index, asset, from, output.value));
+        } else {
+            // Read the destination only after the source is written, so that sending to self works.
+            let balance = state.lenient(state.balance(output.to, asset));
+            let balance = checked_add(balance, output.value).ok_or(TxnError::BalanceOverflow)?;
+            // A zero value transfer to an absent account must not create it.
+            state.set_balance(output.to, asset, balance);
+            let from = if mint { H256::zero() } else { from };
+            logs.push(Log::transfer(asset, from, output.to, output.value));
+        }
+    }
+    Ok((true, logs))
+}
+
+/// `U256` lacks `checked_add`, so here is one.
+fn checked_add(a: U256, b: U256) -> Option<U256> {
+    match a.overflowing_add(b) {
+        (sum, false) => Some(sum),
+        (_, true) => None,
+    }
+}
+
+/// Creates the genesis block and state of the test chain. See [`GenesisSpec::test`].
 pub fn build_genesis() -> (Block, State<InMemoryBackend>) {
-    let backend = InMemoryBackend::new();
-    let mut state = State::empty(backend);
-    state.set(ALICE, 100);
-    state.set(BOB, 90);
-    state.set(CHARLIE, 80);
-    state.set(DAVE, 70);
-    state.set(EVE, 60);
-
-    let block = Block {
-        number: 0,
-        parent: H256::zero(),
-        state_root: state.root(),
-        txns: Vec::new(),
-    };
-
-    (block, state)
+    GenesisSpec::test().build()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+
+    /// Creates a block on top of the given one. The state root is left unset.
+    fn child_block(parent: &Block, beneficiary: H256, txns: Vec<impl Into<TxnEnvelope>>) -> Block {
+        let txns: Vec<TxnEnvelope> = txns.into_iter().map(Into::into).collect();
+        let (txns_root, _) = txns_trie(&txns);
+        Block {
+            header: Header {
+                chain_id: parent.header.chain_id,
+                number: parent.header.number + 1,
+                timestamp: parent.header.timestamp + 12,
+                parent: parent.hash(),
+                state_root: H256::zero(),
+                beneficiary,
+                gas_limit: parent.header.gas_limit,
+                deposits_hash: deposits_hash(&[]),
+                txns_root,
+                receipts_root: EMPTY_TRIE_HASH,
+                withdrawals_root: parent.header.withdrawals_root,
+                extra_data: Vec::new(),
+            },
+            deposits: Vec::new(),
+            txns,
+        }
+    }
+
+    fn balance(state: &State, address: H256) -> u64 {
+        state
+            .get_account(address)
+            .unwrap()
+            .unwrap_or_default()
+            .balance
+            .as_u64()
+    }
 
     #[test]
     fn it_works() {
-        let (_block, mut state) = build_genesis();
+        let (genesis, mut state) = build_genesis();
         let genesis_root = state.root();
 
         // Send a transaction.
-        let txn = Txn {
-            from: ALICE,
-            to: BOB,
-            value: 10,
-        };
-        apply_txn(&mut state, &txn).unwrap();
-        assert_eq!(state.get(ALICE), Some(90));
-        assert_eq!(state.get(BOB), Some(100));
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 0).sign(&ALICE_SECRET);
+        apply_txn(&mut state, &genesis.header, &txn).unwrap();
+        assert_eq!(balance(&state, ALICE), 90);
+        assert_eq!(balance(&state, BOB), 100);
+        // The root only changes once the writes are committed.
+        assert_eq!(state.root(), genesis_root);
+        state.commit();
         assert_ne!(state.root(), genesis_root);
 
-        // Then send the inverse transaction. That should return us to the initial state.
-        let txn = Txn {
-            from: BOB,
+        // Then send the inverse transaction. That should return the balances to the initial ones,
+        // but the nonces were bumped so the root is different.
+        let txn = Txn::new(TEST_CHAIN_ID, BOB, 0, ALICE, 10.into(), 0).sign(&BOB_SECRET);
+        apply_txn(&mut state, &genesis.header, &txn).unwrap();
+        assert_eq!(balance(&state, ALICE), 100);
+        assert_eq!(balance(&state, BOB), 90);
+        assert_eq!(state.get_account(ALICE).unwrap().unwrap().nonce, 1);
+        assert_eq!(state.get_account(BOB).unwrap().unwrap().nonce, 1);
+        state.commit();
+        assert_ne!(state.root(), genesis_root);
+    }
+
+    #[test]
+    fn replayed_txn_is_rejected() {
+        let (genesis, mut state) = build_genesis();
+
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 0).sign(&ALICE_SECRET);
+        apply_txn(&mut state, &genesis.header, &txn).unwrap();
+        state.commit();
+        let root = state.root();
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::InvalidNonce)
+        );
+        state.commit();
+        assert_eq!(state.root(), root);
+
+        // A nonce from the future is rejected as well.
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 2, BOB, 10.into(), 0).sign(&ALICE_SECRET);
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::InvalidNonce)
+        );
+    }
+
+    #[test]
+    fn unaffordable_fee_rejects_block() {
+        let (genesis, mut state) = build_genesis();
+        let genesis_root = state.root();
+        let block = child_block(
+            &genesis,
+            CHARLIE,
+            vec![
+                Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 0).sign(&ALICE_SECRET),
+                Txn::new(TEST_CHAIN_ID, EVE, 0, ALICE, 0.into(), 61).sign(&EVE_SECRET),
+            ],
+        );
+        assert_eq!(
+            execute(&mut state, &block),
+            Err(BlockError::InvalidTxn {
+                index: 1,
+                error: TxnError::InsufficientFunds,
+            })
+        );
+        // The first transaction was reverted with the block.
+        assert_eq!(balance(&state, ALICE), 100);
+        state.commit();
+        assert_eq!(state.root(), genesis_root);
+    }
+
+    #[test]
+    fn state_is_reused_after_rejected_block() {
+        let (genesis, mut state) = build_genesis();
+        let transfer = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 1).sign(&ALICE_SECRET);
+        let overdrawn = Txn::new(TEST_CHAIN_ID, EVE, 0, ALICE, 0.into(), 61).sign(&EVE_SECRET);
+        let rejected = child_block(&genesis, CHARLIE, vec![transfer.clone(), overdrawn]);
+        assert!(execute(&mut state, &rejected).is_err());
+
+        // The same state executes the next block as if the rejected one had never been executed.
+        let block = child_block(&genesis, CHARLIE, vec![transfer]);
+        let receipts = execute(&mut state, &block).unwrap();
+        let mut expected = build_genesis().1;
+        assert_eq!(receipts, execute(&mut expected, &block).unwrap());
+        assert_eq!(state.root(), expected.root());
+        assert_eq!(state.withdrawals_root(), expected.withdrawals_root());
+        assert_eq!(balance(&state, ALICE), 89);
+    }
+
+    #[test]
+    fn checkpoints_undo_writes() {
+        let (_genesis, mut state) = build_genesis();
+        let genesis_root = state.root();
+        let gold = keccak256(b"gold");
+        let key = keccak256(b"key");
+
+        let outer = state.checkpoint();
+        state.set_balance(ALICE, NATIVE_ASSET, 1.into());
+        let inner = state.checkpoint();
+        state.set_balance(ALICE, NATIVE_ASSET, 2.into());
+        state.set_balance(BOB, gold, 3.into());
+        state.storage_set(CHARLIE, key, b"value");
+        state.revert_to(inner);
+        assert_eq!(balance(&state, ALICE), 1);
+        assert_eq!(state.balance(BOB, gold), Ok(0.into()));
+        assert_eq!(state.storage_get(CHARLIE, key), Ok(None));
+        state.revert_to(outer);
+        assert_eq!(balance(&state, ALICE), 100);
+
+        // Nothing was committed, so the trie is untouched.
+        state.commit();
+        assert_eq!(state.root(), genesis_root);
+
+        state.set_balance(ALICE, NATIVE_ASSET, 2.into());
+        state.commit();
+        assert_ne!(state.root(), genesis_root);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalidated by a commit")]
+    fn commit_invalidates_checkpoints() {
+        let (_genesis, mut state) = build_genesis();
+        let checkpoint = state.checkpoint();
+        state.set_balance(ALICE, NATIVE_ASSET, 2.into());
+        state.commit();
+        state.revert_to(checkpoint);
+    }
+
+    #[test]
+    fn test_accounts_match_secrets() {
+        assert_eq!(address(&ALICE_SECRET), ALICE);
+        assert_eq!(address(&BOB_SECRET), BOB);
+        assert_eq!(address(&CHARLIE_SECRET), CHARLIE);
+        assert_eq!(address(&DAVE_SECRET), DAVE);
+        assert_eq!(address(&EVE_SECRET), EVE);
+    }
+
+    #[test]
+    fn txn_signed_by_someone_else_is_rejected() {
+        let (genesis, mut state) = build_genesis();
+        let genesis_root = state.root();
+
+        // Unsigned.
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 0);
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::InvalidSignature)
+        );
+
+        // Signed by EVE on behalf of ALICE.
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 0).sign(&EVE_SECRET);
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::InvalidSignature)
+        );
+
+        state.commit();
+        assert_eq!(state.root(), genesis_root);
+    }
+
+    #[test]
+    fn fees_are_credited_to_beneficiary() {
+        let (genesis, mut state) = build_genesis();
+        let block = child_block(
+            &genesis,
+            CHARLIE,
+            vec![
+                Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 2).sign(&ALICE_SECRET),
+                Txn::new(TEST_CHAIN_ID, BOB, 0, DAVE, 5.into(), 3).sign(&BOB_SECRET),
+            ],
+        );
+        let receipts = execute(&mut state, &block).unwrap();
+        assert_eq!(receipts[0].cumulative_fees, 2);
+        assert_eq!(receipts[1].cumulative_fees, 5);
+        assert_eq!(balance(&state, ALICE), 88);
+        assert_eq!(balance(&state, BOB), 92);
+        assert_eq!(balance(&state, DAVE), 75);
+        assert_eq!(balance(&state, CHARLIE), 85);
+    }
+
+    #[test]
+    fn fee_must_be_covered_by_balance() {
+        let (genesis, mut state) = build_genesis();
+        let genesis_root = state.root();
+
+        let txn = Txn::new(TEST_CHAIN_ID, EVE, 0, ALICE, 0.into(), 61).sign(&EVE_SECRET);
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::InsufficientFunds)
+        );
+        let txn = Txn::new(TEST_CHAIN_ID, EVE, 0, ALICE, 1.into(), u64::MAX).sign(&EVE_SECRET);
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::InsufficientFunds)
+        );
+        state.commit();
+        assert_eq!(state.root(), genesis_root);
+    }
+
+    #[test]
+    fn block_from_header_fetches_txns() {
+        let (genesis, _state) = build_genesis();
+        let mut txns = (0..20)
+            .map(|nonce| {
+                Txn::new(TEST_CHAIN_ID, ALICE, nonce, BOB, 1.into(), 0)
+                    .sign(&ALICE_SECRET)
+                    .into()
+            })
+            .collect::<Vec<TxnEnvelope>>();
+        let outputs = vec![Output {
             to: ALICE,
-            value: 10,
+            value: 1.into(),
+        }];
+        txns.push(
+            BatchTxn::new(TEST_CHAIN_ID, BOB, 0, outputs, 0)
+                .sign(&BOB_SECRET)
+                .into(),
+        );
+        let deposits = vec![Deposit {
+            to: DAVE,
+            value: 5.into(),
+        }];
+        let mut block = child_block(&genesis, CHARLIE, txns.clone());
+        block.header.deposits_hash = deposits_hash(&deposits);
+        // Enough for all the transactions, which are not executed here.
+        block.header.gas_limit = Gas::from(10 * TEST_GAS_LIMIT);
+
+        let (_, mut nodes) = txns_trie(&txns);
+        nodes.insert(block.header.deposits_hash, rlp::encode_list(&deposits));
+        let mut backend = InMemoryBackend::new();
+        backend.apply_changes(nodes, Default::default());
+        let fetched = Block::from_header(block.header.clone(), &backend).unwrap();
+        assert_eq!(fetched.hash(), block.hash());
+        assert_eq!(fetched.deposits, deposits);
+        assert_eq!(fetched.txns, txns);
+
+        // Fetching stops as soon as the deposits and the transactions could exceed the gas limit.
+        let mut header = block.header.clone();
+        header.gas_limit = deposits_gas(1) + max_txn_gas(&txns[0]);
+        assert!(matches!(
+            Block::from_header(header.clone(), &backend),
+            Err(BlockError::GasLimitExceeded { index: 1 })
+        ));
+        header.gas_limit = deposits_gas(1) - Gas::one();
+        assert!(matches!(
+            Block::from_header(header, &backend),
+            Err(BlockError::DepositsGasLimitExceeded)
+        ));
+
+        // Without the trie nodes the transactions cannot be fetched.
+        assert!(matches!(
+            Block::from_header(block.header.clone(), &InMemoryBackend::new()),
+            Err(BlockError::MissingTrieNode(_))
+        ));
+
+        // A root with a gap in the indices doesn't pass for the transactions before the gap.
+        let gapped: BTreeMap<Vec<u8>, Vec<u8>> = [0, 1, 3]
+            .into_iter()
+            .map(|index| {
+                (
+                    primitives::index_key(index),
+                    rlp::encode(&txns[index as usize]),
+                )
+            })
+            .collect();
+        let (root, change) = trie::build(&gapped);
+        let mut backend = InMemoryBackend::new();
+        backend.apply_changes(change.adds, Default::default());
+        backend.apply_changes(
+            [(block.header.deposits_hash, rlp::encode_list(&deposits))].into(),
+            Default::default(),
+        );
+        block.header.txns_root = root;
+        assert!(matches!(
+            Block::from_header(block.header, &backend),
+            Err(BlockError::TxnsRootMismatch)
+        ));
+    }
+
+    #[test]
+    fn overdrawn_txn_fails_but_pays_fee() {
+        let (genesis, mut state) = build_genesis();
+        let block = child_block(
+            &genesis,
+            CHARLIE,
+            vec![
+                Txn::new(TEST_CHAIN_ID, EVE, 0, ALICE, 60.into(), 1).sign(&EVE_SECRET),
+                Txn::new(TEST_CHAIN_ID, EVE, 1, ALICE, 50.into(), 1).sign(&EVE_SECRET),
+            ],
+        );
+        let receipts = execute(&mut state, &block).unwrap();
+        // The failed transaction only reads the sender twice and writes it once, for the nonce.
+        let failed_gas = txn_gas(TrieAccesses {
+            reads: 2,
+            writes: 1,
+        });
+        let gas = txn_gas(TrieAccesses {
+            reads: 5,
+            writes: 3,
+        });
+        assert_eq!(
+            receipts,
+            vec![
+                Receipt {
+                    success: false,
+                    fee: 1,
+                    cumulative_fees: 1,
+                    gas_used: failed_gas,
+                    cumulative_gas_used: failed_gas,
+                    logs: vec![],
+                },
+                Receipt {
+                    success: true,
+                    fee: 1,
+                    cumulative_fees: 2,
+                    gas_used: gas,
+                    cumulative_gas_used: failed_gas + gas,
+                    logs: vec![Log::transfer(NATIVE_ASSET, EVE, ALICE, 50.into())],
+                },
+            ]
+        );
+        assert_eq!(balance(&state, EVE), 8);
+        assert_eq!(balance(&state, ALICE), 150);
+        assert_eq!(state.get_account(EVE).unwrap().unwrap().nonce, 2);
+    }
+
+    #[test]
+    fn mislinked_block_is_rejected() {
+        let (genesis, state) = build_genesis();
+        let txns = vec![Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 1).sign(&ALICE_SECRET)];
+        let mut block = child_block(&genesis, CHARLIE, txns);
+        let mut post_state = state.clone();
+        let receipts = execute(&mut post_state, &block).unwrap();
+        block.header.state_root = post_state.root();
+        block.header.receipts_root = receipts_trie(&receipts).0;
+        validate_and_execute(&mut state.clone(), &genesis.header, &block).unwrap();
+
+        let mut wrong_chain_id = block.header.clone();
+        wrong_chain_id.chain_id = TEST_CHAIN_ID + 1;
+        let mut wrong_gas_limit = block.header.clone();
+        wrong_gas_limit.gas_limit = Gas::max_value();
+        let mut wrong_number = block.header.clone();
+        wrong_number.number = 2;
+        let mut wrong_timestamp = block.header.clone();
+        wrong_timestamp.timestamp = genesis.header.timestamp;
+        let mut wrong_parent = block.header.clone();
+        wrong_parent.parent = H256::zero();
+        let mut wrong_state_root = block.header.clone();
+        wrong_state_root.state_root = genesis.header.state_root;
+        let mut wrong_receipts_root = block.header.clone();
+        wrong_receipts_root.receipts_root = EMPTY_TRIE_HASH;
+        let mut wrong_withdrawals_root = block.header.clone();
+        wrong_withdrawals_root.withdrawals_root = block.header.receipts_root;
+        for (header, error) in [
+            (wrong_chain_id, BlockError::InvalidChainId),
+            (wrong_gas_limit, BlockError::InvalidGasLimit),
+            (wrong_number, BlockError::InvalidNumber),
+            (wrong_timestamp, BlockError::InvalidTimestamp),
+            (wrong_parent, BlockError::InvalidParentHash),
+            (wrong_state_root, BlockError::StateRootMismatch),
+            (wrong_receipts_root, BlockError::ReceiptsRootMismatch),
+            (wrong_withdrawals_root, BlockError::WithdrawalsRootMismatch),
+        ] {
+            let block = Block {
+                header,
+                deposits: Vec::new(),
+                txns: block.txns.clone(),
+            };
+            assert_eq!(
+                validate_and_execute(&mut state.clone(), &genesis.header, &block),
+                Err(error)
+            );
+        }
+    }
+
+    #[test]
+    fn gas_limit_is_enforced() {
+        let (genesis, state) = build_genesis();
+        let txns = vec![
+            Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 1).sign(&ALICE_SECRET),
+            Txn::new(TEST_CHAIN_ID, BOB, 0, CHARLIE, 10.into(), 1).sign(&BOB_SECRET),
+        ];
+        let mut block = child_block(&genesis, EVE, txns);
+        let receipts = execute(&mut state.clone(), &block).unwrap();
+        assert!(receipts[0].gas_used > Gas::from(TXN_BASE_GAS));
+        assert_eq!(
+            receipts[1].cumulative_gas_used,
+            receipts[0].gas_used + receipts[1].gas_used
+        );
+
+        // A transaction is only run if the most gas it can use fits in what is left.
+        let max_gas = max_txn_gas(&block.txns[1]);
+        assert!(max_gas >= receipts[1].gas_used);
+        block.header.gas_limit = receipts[0].gas_used + max_gas;
+        execute(&mut state.clone(), &block).unwrap();
+        block.header.gas_limit = receipts[0].gas_used + max_gas - Gas::one();
+        assert_eq!(
+            execute(&mut state.clone(), &block).err(),
+            Some(BlockError::GasLimitExceeded { index: 1 })
+        );
+
+        // Deposits count against the gas limit, before any transaction.
+        block.deposits = vec![
+            Deposit {
+                to: BOB,
+                value: 1.into(),
+            };
+            2
+        ];
+        block.header.deposits_hash = deposits_hash(&block.deposits);
+        block.header.gas_limit = deposits_gas(2) + receipts[0].gas_used + max_gas;
+        execute(&mut state.clone(), &block).unwrap();
+        block.header.gas_limit = deposits_gas(2) - Gas::one();
+        assert_eq!(
+            execute(&mut state.clone(), &block).err(),
+            Some(BlockError::DepositsGasLimitExceeded)
+        );
+
+        // A batch pays for every output.
+        let outputs = |n| {
+            vec![
+                Output {
+                    to: BOB,
+                    value: 1.into(),
+                };
+                n
+            ]
         };
-        apply_txn(&mut state, &txn).unwrap();
-        assert_eq!(state.get(ALICE), Some(100));
-        assert_eq!(state.get(BOB), Some(90));
+        let gas = |n| {
+            let txn = BatchTxn::new(TEST_CHAIN_ID, ALICE, 0, outputs(n), 1).sign(&ALICE_SECRET);
+            apply_batch_txn(&mut state.clone(), &txn).unwrap().gas_used
+        };
+        assert!(gas(2) > gas(1));
+    }
+
+    #[test]
+    fn balance_overflow_is_an_error() {
+        let (genesis, mut state) = build_genesis();
+        state.set_account(
+            BOB,
+            &Account {
+                nonce: 0,
+                balance: U256::max_value(),
+                ..Default::default()
+            },
+        );
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 1.into(), 0).sign(&ALICE_SECRET);
+        assert_eq!(
+            apply_txn(&mut state, &genesis.header, &txn),
+            Err(TxnError::BalanceOverflow)
+        );
+    }
+
+    #[test]
+    fn genesis_spec_builds_same_state_as_inserting_accounts() {
+        let mut spec = GenesisSpec::test();
+        spec.number = 42;
+        spec.extra_data = b"hello".to_vec();
+        for i in 0..1000u64 {
+            spec.alloc
+                .insert(keccak256(&i.to_be_bytes()), U256::from(i));
+        }
+
+        let decoded: GenesisSpec = rlp::decode(&rlp::encode(&spec));
+        assert_eq!(decoded, spec);
+        // Trailing items are not canonical.
+        let encoded = rlp::encode(&spec);
+        let items = rlp::Rlp::new(&encoded);
+        let mut s = rlp::RlpStream::new_list(8);
+        for item in items.iter() {
+            s.append_raw(item.as_raw(), 1);
+        }
+        s.append(&0u64);
+        assert_eq!(
+            rlp::UntrustedRlp::new(&s.out()).as_val::<GenesisSpec>(),
+            Err(rlp::DecoderError::RlpIncorrectListLen)
+        );
+
+        let (block, state) = spec.build();
+        let mut expected = State::empty(InMemoryBackend::new());
+        expected.set_admin(spec.admin);
+        for (address, balance) in &spec.alloc {
+            expected.put_account(
+                *address,
+                &Account {
+                    nonce: 0,
+                    balance: *balance,
+                    ..Default::default()
+                },
+            );
+        }
+        expected.commit();
+        assert_eq!(state.root(), expected.root());
+        assert_eq!(block.header.state_root, expected.root());
+        assert_eq!(block.header.number, 42);
+        assert_eq!(block.header.extra_data, b"hello");
+        assert_eq!(balance(&state, ALICE), 100);
+    }
+
+    #[test]
+    fn emptied_account_is_removed() {
+        let (_block, mut state) = build_genesis();
+        let genesis_root = state.root();
+        let stranger = H256([0xee; 32]);
+
+        // Fund an account and then drain it. Since it never sent anything, it is empty again and
+        // the state must be the same as if it never existed.
+        state.set_account(
+            stranger,
+            &Account {
+                nonce: 0,
+                balance: 50.into(),
+                ..Default::default()
+            },
+        );
+        state.commit();
+        assert_ne!(state.root(), genesis_root);
+        state.put_account(stranger, &Account::default());
+        assert_eq!(state.get_account(stranger), Ok(None));
+        state.commit();
+        assert_eq!(state.root(), genesis_root);
+
+        // Removing an absent account is a no-op.
+        state.remove(stranger);
+        state.commit();
         assert_eq!(state.root(), genesis_root);
+
+        // Remove all accounts and put them back.
+        let accounts: Vec<_> = [ALICE, BOB, CHARLIE, DAVE, EVE]
+            .into_iter()
+            .map(|address| (address, state.get_account(address).unwrap().unwrap()))
+            .collect();
+        for (address, _) in &accounts {
+            state.remove(*address);
+        }
+        let mut admin_only = State::empty(InMemoryBackend::new());
+        admin_only.set_admin(DAVE);
+        admin_only.commit();
+        state.commit();
+        assert_eq!(state.root(), admin_only.root());
+        for (address, account) in accounts.iter().rev() {
+            state.put_account(*address, account);
+        }
+        state.commit();
+        assert_eq!(state.root(), genesis_root);
+    }
+
+    #[test]
+    fn zero_value_transfer_does_not_create_account() {
+        let (genesis, mut state) = build_genesis();
+        let stranger = H256([0xee; 32]);
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, stranger, 0.into(), 0).sign(&ALICE_SECRET);
+        assert!(
+            apply_txn(&mut state, &genesis.header, &txn)
+                .unwrap()
+                .success
+        );
+        assert_eq!(state.get_account(stranger), Ok(None));
+
+        let (_block, mut expected) = build_genesis();
+        expected.set_account(
+            ALICE,
+            &Account {
+                nonce: 1,
+                balance: 100.into(),
+                ..Default::default()
+            },
+        );
+        state.commit();
+        expected.commit();
+        assert_eq!(state.root(), expected.root());
+    }
+
+    #[test]
+    fn txn_for_other_chain_is_rejected() {
+        let (genesis, state) = build_genesis();
+        let txn = Txn::new(TEST_CHAIN_ID + 1, ALICE, 0, BOB, 10.into(), 0).sign(&ALICE_SECRET);
+        let block = child_block(&genesis, CHARLIE, vec![txn.clone()]);
+        assert_eq!(
+            execute(&mut state.clone(), &block),
+            Err(BlockError::InvalidTxn {
+                index: 0,
+                error: TxnError::InvalidChainId
+            })
+        );
+
+        // The chain id is signed, so the transaction cannot be moved to another chain.
+        let mut moved = txn;
+        moved.chain_id = TEST_CHAIN_ID;
+        assert_eq!(
+            apply_txn(&mut state.clone(), &genesis.header, &moved),
+            Err(TxnError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn deposits_are_minted_before_txns() {
+        let (genesis, state) = build_genesis();
+        // ALICE can only afford the transfer thanks to the deposit in the same block.
+        let txns = vec![Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 150.into(), 0).sign(&ALICE_SECRET)];
+        let mut block = child_block(&genesis, CHARLIE, txns);
+        block.deposits = vec![Deposit {
+            to: ALICE,
+            value: 100.into(),
+        }];
+
+        let mut post_state = state.clone();
+        let receipts = execute(&mut post_state, &block).unwrap();
+        assert!(receipts[0].success);
+        assert_eq!(balance(&post_state, ALICE), 50);
+        assert_eq!(balance(&post_state, BOB), 240);
+
+        let mut rich_state = state;
+        rich_state.set_account(
+            BOB,
+            &Account {
+                nonce: 0,
+                balance: U256::max_value(),
+                ..Default::default()
+            },
+        );
+        block.deposits.push(Deposit {
+            to: BOB,
+            value: 1.into(),
+        });
+        assert_eq!(
+            execute(&mut rich_state, &block),
+            Err(BlockError::DepositOverflow { index: 1 })
+        );
+    }
+
+    #[test]
+    fn withdrawal_is_provable() {
+        let (genesis, mut state) = build_genesis();
+        for (index, (from, secret, value)) in [(ALICE, ALICE_SECRET, 30), (BOB, BOB_SECRET, 90)]
+            .into_iter()
+            .enumerate()
+        {
+            let txn = Txn::new(TEST_CHAIN_ID, from, 0, WITHDRAWALS_ADDRESS, value.into(), 0)
+                .sign(&secret);
+            let receipt = apply_txn(&mut state, &genesis.header, &txn).unwrap();
+            assert_eq!(
+                receipt.logs,
+                vec![Log::withdrawal(
+                    index as u64,
+                    NATIVE_ASSET,
+                    from,
+                    value.into()
+                )]
+            );
+        }
+        // The withdrawn value is burned, and the withdrawals are only counted in their own trie.
+        assert_eq!(balance(&state, ALICE), 70);
+        assert_eq!(balance(&state, BOB), 0);
+        assert_eq!(state.get_account(WITHDRAWALS_ADDRESS), Ok(None));
+
+        // The proof is enough to look up the withdrawal given the withdrawals root.
+        state.commit();
+        let proof = state.withdrawal_proof(1).unwrap().unwrap();
+        let nodes: BTreeMap<H256, Vec<u8>> = proof
+            .into_iter()
+            .map(|node| (keccak256(&node), node))
+            .collect();
+        let withdrawal = trie::get(state.withdrawals_root(), &&nodes, &primitives::index_key(1))
+            .unwrap()
+            .map(rlp::decode::<Withdrawal>);
+        assert_eq!(
+            withdrawal,
+            Some(Withdrawal {
+                from: BOB,
+                asset: NATIVE_ASSET,
+                value: 90.into(),
+            })
+        );
+        assert_eq!(state.withdrawal(1), Ok(withdrawal));
+        assert_eq!(state.withdrawal_proof(2), Ok(None));
+    }
+
+    #[test]
+    fn admin_mints_assets() {
+        let (genesis, mut state) = build_genesis();
+        let genesis_root = state.root();
+        let gold = keccak256(b"gold");
+        let admin = state.admin().unwrap().unwrap();
+        assert_eq!(admin, DAVE);
+
+        // Only the admin can mint.
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 1)
+            .with_asset(gold)
+            .sign(&ALICE_SECRET);
+        assert!(
+            !apply_txn(&mut state, &genesis.header, &txn)
+                .unwrap()
+                .success
+        );
+        let txn = Txn::new(TEST_CHAIN_ID, DAVE, 0, ALICE, 50.into(), 1)
+            .with_asset(gold)
+            .sign(&DAVE_SECRET);
+        let receipt = apply_txn(&mut state, &genesis.header, &txn).unwrap();
+        assert_eq!(
+            receipt.logs,
+            vec![Log::transfer(gold, H256::zero(), ALICE, 50.into())]
+        );
+        assert_eq!(state.balance(ALICE, gold), Ok(50.into()));
+        assert_eq!(state.balance(DAVE, gold), Ok(0.into()));
+        assert_eq!(balance(&state, DAVE), 69);
+
+        // The minted asset can be transferred and withdrawn, the fees are paid in the native asset.
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 1, BOB, 20.into(), 1)
+            .with_asset(gold)
+            .sign(&ALICE_SECRET);
+        assert!(
+            apply_txn(&mut state, &genesis.header, &txn)
+                .unwrap()
+                .success
+        );
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 2, WITHDRAWALS_ADDRESS, 30.into(), 1)
+            .with_asset(gold)
+            .sign(&ALICE_SECRET);
+        assert!(
+            apply_txn(&mut state, &genesis.header, &txn)
+                .unwrap()
+                .success
+        );
+        assert_eq!(state.balance(ALICE, gold), Ok(0.into()));
+        assert_eq!(state.balance(BOB, gold), Ok(20.into()));
+        assert_eq!(balance(&state, ALICE), 97);
+        assert_eq!(
+            state.withdrawal(0),
+            Ok(Some(Withdrawal {
+                from: ALICE,
+                asset: gold,
+                value: 30.into(),
+            }))
+        );
+
+        // Zero balances of assets are not stored.
+        state.set_balance(BOB, gold, 0.into());
+        let mut expected = build_genesis().1;
+        for (address, nonce, native) in [(ALICE, 3, 97), (DAVE, 1, 69)] {
+            expected.set_account(
+                address,
+                &Account {
+                    nonce,
+                    balance: native.into(),
+                    ..Default::default()
+                },
+            );
+        }
+        expected.push_withdrawal(&state.withdrawal(0).unwrap().unwrap());
+        state.commit();
+        expected.commit();
+        assert_eq!(state.root(), expected.root());
+        assert_ne!(state.root(), genesis_root);
+    }
+
+    #[test]
+    fn transfers_to_reserved_keys_are_plain_transfers() {
+        let (genesis, mut state) = build_genesis();
+        let gold = keccak256(b"gold");
+        let txn = Txn::new(TEST_CHAIN_ID, DAVE, 0, ALICE, 50.into(), 1)
+            .with_asset(gold)
+            .sign(&DAVE_SECRET);
+        assert!(
+            apply_txn(&mut state, &genesis.header, &txn)
+                .unwrap()
+                .success
+        );
+        state.commit();
+
+        // Neither the key of the admin nor the one of a balance are accounts, but funding them
+        // must not clobber what is stored under those keys.
+        let mut nonce = 0;
+        for to in [keccak256(b"admin"), asset_key(gold)] {
+            let txn = Txn::new(TEST_CHAIN_ID, ALICE, nonce, to, 5.into(), 1).sign(&ALICE_SECRET);
+            assert!(
+                apply_txn(&mut state, &genesis.header, &txn)
+                    .unwrap()
+                    .success
+            );
+            let txn = Txn::new(TEST_CHAIN_ID, ALICE, nonce + 1, to, 7.into(), 1)
+                .with_asset(gold)
+                .sign(&ALICE_SECRET);
+            assert!(
+                apply_txn(&mut state, &genesis.header, &txn)
+                    .unwrap()
+                    .success
+            );
+            state.commit();
+            assert_eq!(balance(&state, to), 5);
+            assert_eq!(state.balance(to, gold), Ok(7.into()));
+            nonce += 2;
+        }
+        assert_eq!(state.admin(), Ok(Some(DAVE)));
+        assert_eq!(state.balance(ALICE, gold), Ok(36.into()));
+        assert_eq!(state.missing_node(), None);
+    }
+
+    #[test]
+    fn batch_txn_is_all_or_nothing() {
+        let (genesis, state) = build_genesis();
+        let outputs = |values: [u64; 3]| {
+            [CHARLIE, DAVE, WITHDRAWALS_ADDRESS]
+                .into_iter()
+                .zip(values)
+                .map(|(to, value)| Output {
+                    to,
+                    value: value.into(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let txns = vec![
+            BatchTxn::new(TEST_CHAIN_ID, ALICE, 0, outputs([10, 20, 30]), 1).sign(&ALICE_SECRET),
+            // BOB can afford each of the outputs but not all of them.
+            BatchTxn::new(TEST_CHAIN_ID, BOB, 0, outputs([40, 40, 40]), 1).sign(&BOB_SECRET),
+        ];
+        let block = child_block(&genesis, EVE, txns);
+
+        let mut post_state = state.clone();
+        let receipts = execute(&mut post_state, &block).unwrap();
+        assert!(receipts[0].success);
+        assert_eq!(receipts[0].logs.len(), 3);
+        assert!(!receipts[1].success);
+        assert!(receipts[1].logs.is_empty());
+        assert_eq!(balance(&post_state, ALICE), 39);
+        assert_eq!(balance(&post_state, BOB), 89);
+        assert_eq!(balance(&post_state, CHARLIE), 90);
+        assert_eq!(balance(&post_state, DAVE), 90);
+        assert_eq!(balance(&post_state, EVE), 62);
+        assert_eq!(post_state.withdrawal(0).unwrap().unwrap().value, 30.into());
+
+        // The signature of a batch can't be passed off as the signature of a transfer.
+        let txn = BatchTxn::new(TEST_CHAIN_ID, ALICE, 0, outputs([1, 0, 0]), 0).sign(&ALICE_SECRET);
+        let mut forged = Txn::new(TEST_CHAIN_ID, ALICE, 0, CHARLIE, 1.into(), 0);
+        forged.signature = txn.signature;
+        assert_eq!(
+            apply_txn(&mut state.clone(), &genesis.header, &forged),
+            Err(TxnError::InvalidSignature)
+        );
+
+        // A plain transfer is still decoded from its original encoding.
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, CHARLIE, 1.into(), 0).sign(&ALICE_SECRET);
+        assert_eq!(
+            rlp::decode::<TxnEnvelope>(&rlp::encode(&txn)),
+            TxnEnvelope::Transfer(txn)
+        );
+    }
+
+    #[test]
+    fn txn_envelope_dispatches_on_type_byte() {
+        let transfer = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 1.into(), 1).sign(&ALICE_SECRET);
+        let outputs = vec![Output {
+            to: BOB,
+            value: 1.into(),
+        }];
+        let batch = BatchTxn::new(TEST_CHAIN_ID, ALICE, 0, outputs, 1).sign(&ALICE_SECRET);
+        let locked = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 1.into(), 1)
+            .with_lock(TimeLock::Number(1))
+            .sign(&ALICE_SECRET);
+        for txn in [
+            TxnEnvelope::from(transfer),
+            TxnEnvelope::from(batch),
+            TxnEnvelope::from(locked),
+            TxnEnvelope::from(
+                StoreTxn::new(TEST_CHAIN_ID, ALICE, 0, H256::zero(), vec![1], 1)
+                    .sign(&ALICE_SECRET),
+            ),
+        ] {
+            let encoded = rlp::encode(&txn);
+            let untrusted = rlp::UntrustedRlp::new(&encoded);
+            match txn.txn_type().type_byte() {
+                None => assert!(untrusted.is_list()),
+                Some(type_byte) => {
+                    assert_eq!(untrusted.data().unwrap()[0], type_byte);
+                    assert_eq!(TxnType::from_type_byte(type_byte), Some(txn.txn_type()));
+                }
+            }
+            assert_eq!(rlp::decode::<TxnEnvelope>(&encoded), txn);
+        }
+
+        // Every type byte identifies exactly one kind.
+        for (i, kind) in TxnType::ALL.into_iter().enumerate() {
+            if let Some(type_byte) = kind.type_byte() {
+                assert!(TxnType::ALL[i + 1..]
+                    .iter()
+                    .all(|other| other.type_byte() != Some(type_byte)));
+            }
+        }
+
+        // Transactions of kinds that are not registered are rejected rather than misinterpreted.
+        for payload in [vec![], vec![0x7f, 0xc0]] {
+            let encoded = rlp::encode(&payload);
+            assert!(rlp::UntrustedRlp::new(&encoded)
+                .as_val::<TxnEnvelope>()
+                .is_err());
+        }
+
+        // A locked transfer only has the typed encoding.
+        let locked = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 1.into(), 1)
+            .with_lock(TimeLock::Timestamp(1))
+            .sign(&ALICE_SECRET);
+        let untyped = rlp::encode(&locked);
+        assert!(rlp::UntrustedRlp::new(&untyped)
+            .as_val::<TxnEnvelope>()
+            .is_err());
+    }
+
+    #[test]
+    fn locked_txn_is_only_valid_once_open() {
+        let (genesis, state) = build_genesis();
+        let txn = |lock| {
+            Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 1)
+                .with_lock(lock)
+                .sign(&ALICE_SECRET)
+        };
+        let opens_at_12 = txn(TimeLock::Timestamp(genesis.header.timestamp + 12));
+        let opens_at_2 = txn(TimeLock::Number(2));
+        let mut block = child_block(&genesis, EVE, vec![opens_at_12.clone()]);
+
+        block.header.timestamp = genesis.header.timestamp + 11;
+        assert_eq!(
+            execute(&mut state.clone(), &block).err(),
+            Some(BlockError::InvalidTxn {
+                index: 0,
+                error: TxnError::Locked
+            })
+        );
+        block.header.timestamp = genesis.header.timestamp + 12;
+        assert!(execute(&mut state.clone(), &block).unwrap()[0].success);
+
+        assert_eq!(
+            apply_txn(&mut state.clone(), &block.header, &opens_at_2),
+            Err(TxnError::Locked)
+        );
+        block.header.number = 2;
+        assert!(
+            apply_txn(&mut state.clone(), &block.header, &opens_at_2)
+                .unwrap()
+                .success
+        );
+
+        // The lock is signed, so it can't be stripped off.
+        let mut unlocked = opens_at_2;
+        unlocked.lock = None;
+        assert_eq!(
+            apply_txn(&mut state.clone(), &genesis.header, &unlocked),
+            Err(TxnError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn store_txn_writes_sender_storage() {
+        let (genesis, state) = build_genesis();
+        let key = keccak256(b"greeting");
+        let txns = vec![
+            StoreTxn::new(TEST_CHAIN_ID, ALICE, 0, key, b"hello".to_vec(), 1).sign(&ALICE_SECRET),
+            StoreTxn::new(TEST_CHAIN_ID, BOB, 0, key, b"hi".to_vec(), 1).sign(&BOB_SECRET),
+        ];
+        let block = child_block(&genesis, EVE, txns);
+        let mut post_state = state.clone();
+        let receipts = execute(&mut post_state, &block).unwrap();
+        assert!(receipts.iter().all(|receipt| receipt.success));
+        assert_eq!(
+            post_state.storage_get(ALICE, key),
+            Ok(Some(b"hello".to_vec()))
+        );
+        assert_eq!(post_state.storage_get(BOB, key), Ok(Some(b"hi".to_vec())));
+        assert_eq!(post_state.storage_get(CHARLIE, key), Ok(None));
+        assert_eq!(balance(&post_state, ALICE), 99);
+
+        // Removing the only key brings back the empty storage root.
+        let txn = StoreTxn::new(TEST_CHAIN_ID, ALICE, 1, key, Vec::new(), 1).sign(&ALICE_SECRET);
+        apply_store_txn(&mut post_state, &txn).unwrap();
+        assert_eq!(post_state.storage_get(ALICE, key), Ok(None));
+        post_state.commit();
+        assert_eq!(
+            post_state.get_account(ALICE).unwrap().unwrap().storage_root,
+            EMPTY_TRIE_HASH
+        );
+
+        // An account with storage is not empty, so it is not removed.
+        let mut state = state;
+        state.storage_set(H256([0xee; 32]), key, b"kept");
+        assert_eq!(
+            state.storage_get(H256([0xee; 32]), key),
+            Ok(Some(b"kept".to_vec()))
+        );
+        state.commit();
+        assert_ne!(
+            state
+                .get_account(H256([0xee; 32]))
+                .unwrap()
+                .unwrap()
+                .storage_root,
+            EMPTY_TRIE_HASH
+        );
+
+        let value = vec![0; MAX_STORAGE_VALUE_LEN + 1];
+        let txn = StoreTxn::new(TEST_CHAIN_ID, ALICE, 0, key, value, 1).sign(&ALICE_SECRET);
+        assert_eq!(
+            apply_store_txn(&mut state, &txn),
+            Err(TxnError::ValueTooLarge)
+        );
+    }
+
+    #[test]
+    fn identical_storage_survives_clearing_a_copy() {
+        let (_, mut state) = build_genesis();
+        let key = keccak256(b"key");
+        state.storage_set(ALICE, key, b"value");
+        state.storage_set(BOB, key, b"value");
+        state.commit();
+        let storage_root =
+            |state: &State, address| state.get_account(address).unwrap().unwrap().storage_root;
+        assert_eq!(storage_root(&state, ALICE), storage_root(&state, BOB));
+
+        // Both storage tries are the same nodes, clearing one must not remove them from the other.
+        state.storage_set(ALICE, key, b"");
+        state.commit();
+        assert_eq!(storage_root(&state, ALICE), EMPTY_TRIE_HASH);
+        assert_eq!(state.storage_get(BOB, key), Ok(Some(b"value".to_vec())));
+    }
+
+    #[test]
+    fn diff_lists_changed_entries() {
+        let (genesis, state) = build_genesis();
+        let stranger = H256([0xee; 32]);
+        let gold = keccak256(b"gold");
+        let key = keccak256(b"key");
+        let txns: Vec<TxnEnvelope> = vec![
+            Txn::new(TEST_CHAIN_ID, ALICE, 0, stranger, 10.into(), 1)
+                .sign(&ALICE_SECRET)
+                .into(),
+            Txn::new(TEST_CHAIN_ID, DAVE, 0, BOB, 5.into(), 1)
+                .with_asset(gold)
+                .sign(&DAVE_SECRET)
+                .into(),
+            StoreTxn::new(TEST_CHAIN_ID, ALICE, 1, key, b"value".to_vec(), 1)
+                .sign(&ALICE_SECRET)
+                .into(),
+            // Sends back what it gets, so that its balance doesn't change.
+            Txn::new(TEST_CHAIN_ID, CHARLIE, 0, CHARLIE, 10.into(), 0)
+                .sign(&CHARLIE_SECRET)
+                .into(),
+        ];
+        let block = child_block(&genesis, EVE, txns);
+
+        let mut post_state = state.clone();
+        let (receipts, diff) = execute_with_diff(&mut post_state, &block).unwrap();
+        let mut expected_state = state.clone();
+        assert_eq!(receipts, execute(&mut expected_state, &block).unwrap());
+        assert_eq!(post_state.root(), expected_state.root());
+
+        let account = |state: &State, address| state.get_account(address).unwrap();
+        let accounts: Vec<_> = diff.accounts.keys().copied().collect();
+        // The storage root of BOB changed along with the balance of gold.
+        let mut expected_accounts = vec![ALICE, BOB, CHARLIE, DAVE, EVE, stranger];
+        expected_accounts.sort();
+        assert_eq!(accounts, expected_accounts);
+        for address in accounts {
+            assert_eq!(diff.accounts[&address].old, account(&state, address));
+            assert_eq!(diff.accounts[&address].new, account(&post_state, address));
+        }
+        // Only the nonce of CHARLIE changed.
+        assert_eq!(
+            diff.accounts[&CHARLIE].old.as_ref().unwrap().balance,
+            diff.accounts[&CHARLIE].new.as_ref().unwrap().balance
+        );
+        assert_eq!(diff.created().collect::<Vec<_>>(), vec![stranger]);
+        assert_eq!(diff.deleted().count(), 0);
+        assert_eq!(
+            diff.assets[&(BOB, gold)],
+            Diff {
+                old: 0.into(),
+                new: 5.into()
+            }
+        );
+        assert_eq!(
+            diff.storage[&(ALICE, key)],
+            Diff {
+                old: None,
+                new: Some(b"value".to_vec())
+            }
+        );
+
+        // Entries that were changed and restored are not listed.
+        let mut state = post_state;
+        state.record_diff();
+        state.set_balance(stranger, NATIVE_ASSET, 0.into());
+        state.set_balance(BOB, gold, 6.into());
+        state.set_balance(BOB, gold, 5.into());
+        let diff = state.take_diff();
+        assert_eq!(diff.deleted().collect::<Vec<_>>(), vec![stranger]);
+        assert!(diff.assets.is_empty());
+        assert_eq!(state.take_diff(), StateDiff::default());
+    }
+
+    #[test]
+    fn diff_lists_only_accounts_touched_by_withdrawal() {
+        let (genesis, mut state) = build_genesis();
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, WITHDRAWALS_ADDRESS, 10.into(), 0)
+            .sign(&ALICE_SECRET);
+        let block = child_block(&genesis, EVE, vec![txn]);
+        let (_, diff) = execute_with_diff(&mut state, &block).unwrap();
+        assert_eq!(diff.accounts.keys().collect::<Vec<_>>(), vec![&ALICE]);
+        assert_eq!(diff.created().count(), 0);
+    }
+
+    #[test]
+    fn recorded_witness_executes_block() {
+        let (genesis, mut state) = build_genesis();
+        let withdraw = |from, secret: &[u8; 32]| {
+            Txn::new(TEST_CHAIN_ID, from, 0, WITHDRAWALS_ADDRESS, 10.into(), 0).sign(secret)
+        };
+        let store = |nonce, key| {
+            let value = b"value".to_vec();
+            StoreTxn::new(TEST_CHAIN_ID, CHARLIE, nonce, key, value, 0).sign(&CHARLIE_SECRET)
+        };
+        let txns: Vec<TxnEnvelope> = vec![
+            withdraw(ALICE, &ALICE_SECRET).into(),
+            store(0, H256([1; 32])).into(),
+        ];
+        let mut parent = child_block(&genesis, EVE, txns);
+        execute(&mut state, &parent).unwrap();
+        parent.header.state_root = state.root();
+        parent.header.withdrawals_root = state.withdrawals_root();
+        let stranger = H256([0xaa; 32]);
+        let txns: Vec<TxnEnvelope> = vec![
+            withdraw(BOB, &BOB_SECRET).into(),
+            Txn::new(TEST_CHAIN_ID, ALICE, 1, stranger, 5.into(), 0)
+                .sign(&ALICE_SECRET)
+                .into(),
+            // The storage trie is not read, it is only needed to add the value.
+            store(1, H256([2; 32])).into(),
+        ];
+        let block = child_block(&parent, EVE, txns);
+
+        let backend = RecordingBackend::new(state.clone().into_backend());
+        let mut recording = State::with_roots(backend, state.root(), state.withdrawals_root());
+        execute(&mut recording, &block).unwrap();
+        let reads = recording.backend_ref().reads();
+        let writes = recording.backend_ref().writes();
+        // The number of withdrawals is read from the withdrawals trie before adding one.
+        assert!(reads.nodes().contains_key(&state.withdrawals_root()));
+        assert!(reads
+            .nodes()
+            .keys()
+            .all(|hash| !writes.nodes().contains_key(hash)));
+        let (_, witness) = recording.into_backend().into_inner();
+        assert_eq!(witness.len(), reads.len() + writes.len());
+
+        // A recording over another one passes on why each node is needed.
+        let inner = RecordingBackend::new(state.clone().into_backend());
+        let backend = RecordingBackend::new(inner);
+        let mut nested = State::with_roots(backend, state.root(), state.withdrawals_root());
+        execute(&mut nested, &block).unwrap();
+        let (inner, _) = nested.into_backend().into_inner();
+        assert!(!writes.is_empty());
+        assert_eq!(inner.writes(), writes);
+        assert_eq!(inner.reads(), reads);
+
+        // The witness alone, sent as RLP, is enough to execute the block.
+        let witness: Witness = rlp::decode(&rlp::encode(&witness));
+        let mut replay = State::with_roots(witness, state.root(), state.withdrawals_root());
+        execute(&mut replay, &block).unwrap();
+        execute(&mut state, &block).unwrap();
+        assert_eq!(replay.root(), state.root());
+        assert_eq!(replay.withdrawals_root(), state.withdrawals_root());
+        assert_eq!(replay.get_account(stranger), state.get_account(stranger));
+    }
+
+    #[test]
+    fn missing_node_is_reported() {
+        let (genesis, state) = build_genesis();
+        let root = state.root();
+        let state = State::with_roots(Witness::new(), root, state.withdrawals_root());
+        assert_eq!(state.get_account(ALICE), Err(MissingNode(root)));
+        assert_eq!(state.balance(ALICE, NATIVE_ASSET), Err(MissingNode(root)));
+        assert_eq!(state.missing_node(), None);
+        // The methods that don't return a `Result` take the missing account as absent.
+        let mut state = state;
+        state.set_balance(ALICE, NATIVE_ASSET, 1.into());
+        assert_eq!(state.missing_node(), Some(root));
+
+        let txn = Txn::new(TEST_CHAIN_ID, ALICE, 0, BOB, 10.into(), 0).sign(&ALICE_SECRET);
+        let block = child_block(&genesis, EVE, vec![txn]);
+        assert_eq!(
+            execute(&mut state, &block),
+            Err(BlockError::MissingTrieNode(root))
+        );
+    }
+
+    #[test]
+    fn witness_is_filled_in_passes() {
+        let (genesis, state) = build_genesis();
+        let stranger = H256([0xaa; 32]);
+        let block = child_block(
+            &genesis,
+            EVE,
+            vec![
+                Txn::new(TEST_CHAIN_ID, ALICE, 0, stranger, 5.into(), 1).sign(&ALICE_SECRET),
+                Txn::new(TEST_CHAIN_ID, BOB, 0, WITHDRAWALS_ADDRESS, 7.into(), 1).sign(&BOB_SECRET),
+            ],
+        );
+        let mut expected = state.clone();
+        execute(&mut expected, &block).unwrap();
+
+        let mut witness = Witness::new();
+        let mut passes = 0;
+        let result = loop {
+            passes += 1;
+            let backend = WitnessBackend::new(witness.clone());
+            let mut replay = State::with_roots(backend, state.root(), state.withdrawals_root());
+            let result = execute(&mut replay, &block);
+            let missing = replay.backend_ref().missing();
+            if missing.is_empty() {
+                break result.map(|_| replay);
+            }
+            assert!(matches!(result, Err(BlockError::MissingTrieNode(_))));
+            for hash in missing {
+                witness.insert(state.backend_ref().get(hash).unwrap().to_vec());
+            }
+        };
+        // Every pass only finds the nodes under the ones found by the previous one.
+        assert!(passes > 2);
+        let replay = result.unwrap();
+        assert_eq!(replay.root(), expected.root());
+        assert_eq!(replay.withdrawals_root(), expected.withdrawals_root());
+
+        // That is the same witness as the one recorded with the whole state.
+        let backend = RecordingBackend::new(state.clone().into_backend());
+        let mut recording = State::with_roots(backend, state.root(), state.withdrawals_root());
+        execute(&mut recording, &block).unwrap();
+        assert_eq!(recording.backend_ref().witness(), witness);
     }
 }
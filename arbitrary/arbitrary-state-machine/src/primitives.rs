@@ -1,80 +1,648 @@
 //! Various types that constitute a block chain.
 
+use crate::error::BlockError;
+use crate::gas::{deposits_gas, max_txn_gas};
+use crate::signature::Signature;
+use crate::state::{Backend, BackendWrapper};
+use alloc::collections::BTreeMap;
+use alloc::vec;
 use alloc::vec::Vec;
-use bigint::H256;
+use bigint::{Gas, H256, U256};
+use rlp_derive::{RlpDecodable, RlpEncodable};
 
+/// The id of the native asset. Its balance is the `balance` of the [`crate::Account`] and it is the
+/// asset fees are paid in. Other assets are created by the admin, see [`crate::GenesisSpec::admin`].
+pub const NATIVE_ASSET: H256 = H256([0x00; 32]);
+
+/// A transfer from one account to another.
+///
+/// Encoded as an RLP list of the fields in their declaration order, with the lock left out if there
+/// is none.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+#[rlp(trailing)]
 pub struct Txn {
+    /// The chain this transaction is meant for. Must be equal to the chain id of the block.
+    pub chain_id: u64,
     pub from: H256,
+    /// Must be equal to the nonce of the `from` account. Prevents replaying the transaction.
+    pub nonce: u64,
     pub to: H256,
-    pub value: u64,
+    /// The asset that is transferred. If `from` is the admin and the asset is not the native one,
+    /// the value is minted instead of being taken from `from`.
+    pub asset: H256,
+    pub value: U256,
+    /// The fee paid by `from` on top of the `value`. Credited to the beneficiary of the block.
+    pub fee: u64,
+    /// The signature of `from` over the [`Txn::signing_hash`].
+    #[rlp(flatten)]
+    pub signature: Signature,
+    /// If set, the transaction is only valid in the blocks the lock is open for. A locked transfer
+    /// is a [`TxnType::LockedTransfer`] in a [`TxnEnvelope`].
+    pub lock: Option<TimeLock>,
 }
 
-impl rlp::Encodable for Txn {
-    fn rlp_append(&self, s: &mut rlp::RlpStream) {
-        s.begin_list(3);
+impl Txn {
+    /// Creates a new unsigned transfer of the native asset. It must be signed with [`Txn::sign`]
+    /// before it can be applied.
+    pub fn new(chain_id: u64, from: H256, nonce: u64, to: H256, value: U256, fee: u64) -> Self {
+        Txn {
+            chain_id,
+            from,
+            nonce,
+            to,
+            asset: NATIVE_ASSET,
+            value,
+            fee,
+            signature: Signature::default(),
+            lock: None,
+        }
+    }
+
+    /// Makes the transaction transfer the given asset instead of the native one.
+    pub fn with_asset(mut self, asset: H256) -> Self {
+        self.asset = asset;
+        self
+    }
+
+    /// Makes the transaction valid only in the blocks the given lock is open for.
+    pub fn with_lock(mut self, lock: TimeLock) -> Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    /// Signs the transaction with the given secret key.
+    pub fn sign(mut self, secret: &[u8; 32]) -> Self {
+        self.signature = Signature::sign(self.signing_hash(), secret);
+        self
+    }
+
+    /// Returns the hash that is signed by the sender, i.e. the hash of all fields except the
+    /// signature. Since it includes the chain id, a transaction signed for one chain is not valid
+    /// on another.
+    ///
+    /// The hash of a locked transfer covers the lock and the type byte, like the one of a
+    /// [`BatchTxn`], so the lock can't be stripped off.
+    pub fn signing_hash(&self) -> H256 {
+        let mut s = rlp::RlpStream::new();
+        s.begin_list(if self.lock.is_some() { 8 } else { 7 });
+        s.append(&self.chain_id);
         s.append(&self.from);
+        s.append(&self.nonce);
         s.append(&self.to);
+        s.append(&self.asset);
         s.append(&self.value);
+        s.append(&self.fee);
+        match &self.lock {
+            Some(lock) => {
+                s.append(lock);
+                keccak256(&typed_payload(TxnType::LockedTransfer, &s.out()))
+            }
+            None => keccak256(&s.out()),
+        }
+    }
+}
+
+/// Restricts the blocks a transaction is valid in. See [`Txn::lock`].
+///
+/// Encoded as an RLP list `[0, timestamp]` or `[1, number]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeLock {
+    /// Only valid in the blocks with at least the given timestamp.
+    Timestamp(u64),
+    /// Only valid in the blocks with at least the given number.
+    Number(u64),
+}
+
+impl TimeLock {
+    /// Returns true if a transaction with this lock is valid in the block with the given header.
+    pub fn is_open(&self, header: &Header) -> bool {
+        match *self {
+            TimeLock::Timestamp(timestamp) => header.timestamp >= timestamp,
+            TimeLock::Number(number) => header.number >= number,
+        }
     }
 }
 
-impl rlp::Decodable for Txn {
+impl rlp::Encodable for TimeLock {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        let (kind, value): (u8, u64) = match *self {
+            TimeLock::Timestamp(timestamp) => (0, timestamp),
+            TimeLock::Number(number) => (1, number),
+        };
+        s.begin_list(2);
+        s.append(&kind);
+        s.append(&value);
+    }
+}
+
+impl rlp::Decodable for TimeLock {
     fn decode(rlp: &rlp::UntrustedRlp) -> Result<Self, rlp::DecoderError> {
-        Ok(Txn {
-            from: rlp.val_at(0)?,
-            to: rlp.val_at(1)?,
-            value: rlp.val_at(2)?,
-        })
+        let value = rlp.val_at(1)?;
+        match rlp.val_at::<u8>(0)? {
+            0 => Ok(TimeLock::Timestamp(value)),
+            1 => Ok(TimeLock::Number(value)),
+            _ => Err(rlp::DecoderError::Custom("unknown time lock kind")),
+        }
     }
 }
 
-impl Txn {
-    pub fn new(from: H256, to: H256, value: u64) -> Self {
-        Txn { from, to, value }
+/// The registry of the kinds of transactions that can be wrapped in a [`TxnEnvelope`].
+///
+/// Every kind but [`TxnType::Transfer`] has a type byte that prefixes its encoding. The transaction
+/// format is evolved by registering a new kind with a new type byte rather than by changing the
+/// encoding of an existing kind, so that the blocks that are already out there keep decoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxnType {
+    /// A [`Txn`] without a lock. Predates the envelope, so it is encoded as a plain RLP list
+    /// without a type byte.
+    Transfer,
+    /// A [`BatchTxn`].
+    Batch,
+    /// A [`Txn`] with a lock.
+    LockedTransfer,
+    /// A [`StoreTxn`].
+    Store,
+}
+
+impl TxnType {
+    /// All the registered kinds.
+    pub const ALL: [TxnType; 4] = [
+        TxnType::Transfer,
+        TxnType::Batch,
+        TxnType::LockedTransfer,
+        TxnType::Store,
+    ];
+
+    /// Returns the type byte of this kind, or `None` for the untyped [`TxnType::Transfer`].
+    pub fn type_byte(self) -> Option<u8> {
+        match self {
+            TxnType::Transfer => None,
+            TxnType::Batch => Some(0x01),
+            TxnType::LockedTransfer => Some(0x02),
+            TxnType::Store => Some(0x03),
+        }
+    }
+
+    /// Returns the kind registered with the given type byte.
+    pub fn from_type_byte(byte: u8) -> Option<TxnType> {
+        TxnType::ALL
+            .into_iter()
+            .find(|kind| kind.type_byte() == Some(byte))
     }
 }
 
-pub struct Block {
+/// Returns the encoding of a typed transaction, i.e. its type byte followed by its RLP encoding.
+fn typed_payload(kind: TxnType, rlp: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(1 + rlp.len());
+    payload.push(kind.type_byte().expect("the transaction kind is typed"));
+    payload.extend_from_slice(rlp);
+    payload
+}
+
+/// A payment to one of the recipients of a [`BatchTxn`].
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Output {
+    pub to: H256,
+    pub value: U256,
+}
+
+/// A transaction that pays many recipients from a single sender at once.
+///
+/// The payments are all-or-nothing: if the sender cannot afford all of them, none is made. The fee
+/// is paid once for the whole batch.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct BatchTxn {
+    pub chain_id: u64,
+    pub from: H256,
+    pub nonce: u64,
+    /// The asset of all the outputs. See [`Txn::asset`].
+    pub asset: H256,
+    #[rlp(list)]
+    pub outputs: Vec<Output>,
+    pub fee: u64,
+    /// The signature of `from` over the [`BatchTxn::signing_hash`].
+    #[rlp(flatten)]
+    pub signature: Signature,
+}
+
+impl BatchTxn {
+    /// Creates a new unsigned batch of payments in the native asset. It must be signed with
+    /// [`BatchTxn::sign`] before it can be applied.
+    pub fn new(chain_id: u64, from: H256, nonce: u64, outputs: Vec<Output>, fee: u64) -> Self {
+        BatchTxn {
+            chain_id,
+            from,
+            nonce,
+            asset: NATIVE_ASSET,
+            outputs,
+            fee,
+            signature: Signature::default(),
+        }
+    }
+
+    /// Makes the batch pay in the given asset instead of the native one.
+    pub fn with_asset(mut self, asset: H256) -> Self {
+        self.asset = asset;
+        self
+    }
+
+    /// Signs the transaction with the given secret key.
+    pub fn sign(mut self, secret: &[u8; 32]) -> Self {
+        self.signature = Signature::sign(self.signing_hash(), secret);
+        self
+    }
+
+    /// Returns the hash that is signed by the sender. It covers the type byte, so that the
+    /// signature cannot be reused for a transaction of another type.
+    pub fn signing_hash(&self) -> H256 {
+        let mut s = rlp::RlpStream::new();
+        s.begin_list(6);
+        s.append(&self.chain_id);
+        s.append(&self.from);
+        s.append(&self.nonce);
+        s.append(&self.asset);
+        s.append_list(&self.outputs);
+        s.append(&self.fee);
+        keccak256(&typed_payload(TxnType::Batch, &s.out()))
+    }
+}
+
+/// The maximum length of a value written by a [`StoreTxn`].
+pub const MAX_STORAGE_VALUE_LEN: usize = 256;
+
+/// A transaction that writes a value into the storage of the sender. See
+/// [`crate::State::storage_get`].
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct StoreTxn {
+    pub chain_id: u64,
+    pub from: H256,
+    pub nonce: u64,
+    pub key: H256,
+    /// The value to store under the key. An empty value removes the key. At most
+    /// [`MAX_STORAGE_VALUE_LEN`] bytes long.
+    pub value: Vec<u8>,
+    pub fee: u64,
+    /// The signature of `from` over the [`StoreTxn::signing_hash`].
+    #[rlp(flatten)]
+    pub signature: Signature,
+}
+
+impl StoreTxn {
+    /// Creates a new unsigned write to the storage of the sender. It must be signed with
+    /// [`StoreTxn::sign`] before it can be applied.
+    pub fn new(chain_id: u64, from: H256, nonce: u64, key: H256, value: Vec<u8>, fee: u64) -> Self {
+        StoreTxn {
+            chain_id,
+            from,
+            nonce,
+            key,
+            value,
+            fee,
+            signature: Signature::default(),
+        }
+    }
+
+    /// Signs the transaction with the given secret key.
+    pub fn sign(mut self, secret: &[u8; 32]) -> Self {
+        self.signature = Signature::sign(self.signing_hash(), secret);
+        self
+    }
+
+    /// Returns the hash that is signed by the sender. Like the one of a [`BatchTxn`], it covers the
+    /// type byte.
+    pub fn signing_hash(&self) -> H256 {
+        let mut s = rlp::RlpStream::new();
+        s.begin_list(6);
+        s.append(&self.chain_id);
+        s.append(&self.from);
+        s.append(&self.nonce);
+        s.append(&self.key);
+        s.append(&self.value);
+        s.append(&self.fee);
+        keccak256(&typed_payload(TxnType::Store, &s.out()))
+    }
+}
+
+/// Any transaction that can be included in a block.
+///
+/// A [`Txn`] is encoded as a plain RLP list, as it always was. Other transactions are encoded, like
+/// in EIP-2718, as an RLP string holding the type byte of their [`TxnType`] followed by the RLP
+/// encoding of the transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TxnEnvelope {
+    Transfer(Txn),
+    Batch(BatchTxn),
+    Store(StoreTxn),
+}
+
+impl rlp::Encodable for TxnEnvelope {
+    fn rlp_append(&self, s: &mut rlp::RlpStream) {
+        let typed = match self {
+            TxnEnvelope::Transfer(txn) if txn.lock.is_none() => {
+                s.append(txn);
+                return;
+            }
+            TxnEnvelope::Transfer(txn) => typed_payload(TxnType::LockedTransfer, &rlp::encode(txn)),
+            TxnEnvelope::Batch(txn) => typed_payload(TxnType::Batch, &rlp::encode(txn)),
+            TxnEnvelope::Store(txn) => typed_payload(TxnType::Store, &rlp::encode(txn)),
+        };
+        s.append(&typed);
+    }
+}
+
+impl rlp::Decodable for TxnEnvelope {
+    fn decode(rlp: &rlp::UntrustedRlp) -> Result<Self, rlp::DecoderError> {
+        if rlp.is_list() {
+            let txn: Txn = rlp.as_val()?;
+            if txn.lock.is_some() {
+                return Err(rlp::DecoderError::Custom("untyped locked transfer"));
+            }
+            return Ok(TxnEnvelope::Transfer(txn));
+        }
+        let (&type_byte, payload) = rlp
+            .data()?
+            .split_first()
+            .ok_or(rlp::DecoderError::Custom("empty typed transaction"))?;
+        let payload = rlp::UntrustedRlp::new(payload);
+        match TxnType::from_type_byte(type_byte) {
+            Some(TxnType::Batch) => Ok(TxnEnvelope::Batch(payload.as_val()?)),
+            Some(TxnType::Store) => Ok(TxnEnvelope::Store(payload.as_val()?)),
+            Some(TxnType::LockedTransfer) => {
+                let txn: Txn = payload.as_val()?;
+                if txn.lock.is_none() {
+                    return Err(rlp::DecoderError::Custom("locked transfer without a lock"));
+                }
+                Ok(TxnEnvelope::Transfer(txn))
+            }
+            Some(TxnType::Transfer) | None => {
+                Err(rlp::DecoderError::Custom("unknown transaction type"))
+            }
+        }
+    }
+}
+
+impl TxnEnvelope {
+    /// Returns the kind of the wrapped transaction.
+    pub fn txn_type(&self) -> TxnType {
+        match self {
+            TxnEnvelope::Transfer(txn) if txn.lock.is_some() => TxnType::LockedTransfer,
+            TxnEnvelope::Transfer(_) => TxnType::Transfer,
+            TxnEnvelope::Batch(_) => TxnType::Batch,
+            TxnEnvelope::Store(_) => TxnType::Store,
+        }
+    }
+
+    /// Returns the chain id of the wrapped transaction.
+    pub fn chain_id(&self) -> u64 {
+        match self {
+            TxnEnvelope::Transfer(txn) => txn.chain_id,
+            TxnEnvelope::Batch(txn) => txn.chain_id,
+            TxnEnvelope::Store(txn) => txn.chain_id,
+        }
+    }
+}
+
+impl From<Txn> for TxnEnvelope {
+    fn from(txn: Txn) -> Self {
+        TxnEnvelope::Transfer(txn)
+    }
+}
+
+impl From<BatchTxn> for TxnEnvelope {
+    fn from(txn: BatchTxn) -> Self {
+        TxnEnvelope::Batch(txn)
+    }
+}
+
+impl From<StoreTxn> for TxnEnvelope {
+    fn from(txn: StoreTxn) -> Self {
+        TxnEnvelope::Store(txn)
+    }
+}
+
+/// A deposit made on L1. It mints the value to the recipient on L2.
+///
+/// Deposits are not signed. They are authorized by being committed to by the L1, see
+/// [`Header::deposits_hash`].
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Deposit {
+    pub to: H256,
+    pub value: U256,
+}
+
+/// Returns the commitment to the given deposits, i.e. the hash of their RLP-encoded list.
+pub fn deposits_hash(deposits: &[Deposit]) -> H256 {
+    keccak256(&rlp::encode_list(deposits))
+}
+
+/// Transfers to this address are withdrawals: the value is burned and a [`Withdrawal`] is recorded
+/// in the withdrawals trie of the state, so that it can be claimed on L1.
+///
+/// Nobody knows a public key that hashes to this address, so nobody can send from it. Nothing is
+/// ever credited to it either, so it has no account.
+pub const WITHDRAWALS_ADDRESS: H256 = H256([0xff; 32]);
+
+/// A withdrawal to L1 recorded in the withdrawals trie. See [`crate::State::withdrawal_proof`].
+///
+/// The withdrawn value can be claimed on L1 by the account with the same public key as `from`,
+/// i.e. by the Ethereum address made of the last 20 bytes of `from`.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Withdrawal {
+    pub from: H256,
+    pub asset: H256,
+    pub value: U256,
+}
+
+/// The block header. The hash of the header identifies the block.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Header {
+    /// The chain this block belongs to. Set by the genesis and inherited by every block.
+    pub chain_id: u64,
     pub number: u64,
+    /// The time the block was produced at, in seconds since the Unix epoch. Must be greater than
+    /// the timestamp of the parent.
+    pub timestamp: u64,
     pub parent: H256,
     pub state_root: H256,
-    pub txns: Vec<Txn>,
+    /// The account that receives the fees of all transactions in this block.
+    pub beneficiary: H256,
+    /// The maximum gas the transactions of this block may use together. Set by the genesis and
+    /// inherited by every block.
+    pub gas_limit: Gas,
+    /// The commitment to the deposits made on L1 that are included in this block. See
+    /// [`deposits_hash`].
+    pub deposits_hash: H256,
+    /// The root of the trie that maps the RLP-encoded index of a transaction to the transaction.
+    /// See [`txns_trie`].
+    pub txns_root: H256,
+    /// The root of the trie that maps the RLP-encoded index of a transaction to its receipt. See
+    /// [`receipts_trie`].
+    pub receipts_root: H256,
+    /// The root of the withdrawals trie after the execution of this block. Unlike the receipts
+    /// trie, it accumulates the withdrawals of all blocks so far.
+    pub withdrawals_root: H256,
+    /// Arbitrary data that is not interpreted by the state transition function.
+    pub extra_data: Vec<u8>,
 }
 
-impl rlp::Encodable for Block {
-    fn rlp_append(&self, s: &mut rlp::RlpStream) {
-        s.begin_list(3);
-        s.append(&self.number);
-        s.append(&self.parent);
-        s.append(&self.state_root);
-        s.append_list(&self.txns);
+impl Header {
+    pub fn hash(&self) -> H256 {
+        keccak256(&self.serialize())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        rlp::encode(self)
     }
 }
 
-impl rlp::Decodable for Block {
-    fn decode(rlp: &rlp::UntrustedRlp) -> Result<Self, rlp::DecoderError> {
+/// A block is a header, the deposits committed to by its `deposits_hash` and the transactions
+/// committed to by its `txns_root`.
+#[derive(RlpEncodable, RlpDecodable)]
+pub struct Block {
+    pub header: Header,
+    #[rlp(list)]
+    pub deposits: Vec<Deposit>,
+    #[rlp(list)]
+    pub txns: Vec<TxnEnvelope>,
+}
+
+impl Block {
+    /// Reassembles the block from the header by fetching the deposits and the transactions from the
+    /// given backend.
+    ///
+    /// The deposits are the preimage of the deposits hash. The transactions are fetched one by one
+    /// from the transactions trie, under consecutive indices starting from 0 until the first
+    /// missing one. The trie must hold nothing else, so the transactions root is checked against
+    /// the fetched transactions.
+    ///
+    /// The deposits and the transactions are metered as they are fetched, like [`crate::execute`]
+    /// does, so that a header committing to more than its gas limit allows is rejected without
+    /// walking all of it.
+    pub fn from_header(header: Header, backend: &impl Backend) -> Result<Block, BlockError> {
+        let deposits_rlp = backend
+            .get(header.deposits_hash)
+            .ok_or(BlockError::MissingTrieNode(header.deposits_hash))?;
+        let deposits_rlp = rlp::UntrustedRlp::new(deposits_rlp);
+        if !deposits_rlp.is_list() {
+            return Err(rlp::DecoderError::RlpExpectedToBeList.into());
+        }
+        let mut deposits = Vec::new();
+        for deposit in deposits_rlp.iter() {
+            if deposits_gas(deposits.len() + 1) > header.gas_limit {
+                return Err(BlockError::DepositsGasLimitExceeded);
+            }
+            deposits.push(deposit.as_val()?);
+        }
+
+        let mut gas = deposits_gas(deposits.len());
+        let mut txns = Vec::new();
+        let db = BackendWrapper(backend);
+        while let Some(txn_rlp) = trie::get(header.txns_root, &db, &index_key(txns.len() as u64))? {
+            let txn = rlp::UntrustedRlp::new(txn_rlp).as_val()?;
+            gas = gas + max_txn_gas(&txn);
+            if gas > header.gas_limit {
+                return Err(BlockError::GasLimitExceeded { index: txns.len() });
+            }
+            txns.push(txn);
+        }
+        // A trie with a gap in the indices or with other keys would otherwise pass for its prefix.
+        if txns_trie(&txns).0 != header.txns_root {
+            return Err(BlockError::TxnsRootMismatch);
+        }
         Ok(Block {
-            number: rlp.val_at(0)?,
-            parent: rlp.val_at(1)?,
-            state_root: rlp.val_at(2)?,
-            txns: rlp.list_at(3)?,
+            header,
+            deposits,
+            txns,
         })
     }
-}
 
-impl Block {
+    /// Returns the hash of the header of this block.
     pub fn hash(&self) -> H256 {
-        H256::from_slice(keccak256(&self.serialize()).as_ref())
+        self.header.hash()
     }
 
     pub fn serialize(&self) -> Vec<u8> {
-        use rlp::Encodable;
-        let mut stream = rlp::RlpStream::new();
-        self.rlp_append(&mut stream);
-        stream.out()
+        rlp::encode(self)
+    }
+}
+
+/// An entry emitted by a transaction that can be proven against the receipts root.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Log {
+    #[rlp(list)]
+    pub topics: Vec<H256>,
+    pub data: Vec<u8>,
+}
+
+impl Log {
+    /// The log emitted by a successful transfer. The topics are `keccak256("Transfer")`, the asset,
+    /// `from` and `to`, and the data is the RLP-encoded value. A mint is logged as a transfer from
+    /// the zero address.
+    pub fn transfer(asset: H256, from: H256, to: H256, value: U256) -> Log {
+        Log {
+            topics: vec![keccak256(b"Transfer"), asset, from, to],
+            data: rlp::encode(&value),
+        }
+    }
+
+    /// The log emitted by a withdrawal. The topics are `keccak256("Withdrawal")`, the asset and
+    /// `from`, and the data is the RLP-encoded list of the index of the withdrawal and the value.
+    pub fn withdrawal(index: u64, asset: H256, from: H256, value: U256) -> Log {
+        let mut data = rlp::RlpStream::new_list(2);
+        data.append(&index);
+        data.append(&value);
+        Log {
+            topics: vec![keccak256(b"Withdrawal"), asset, from],
+            data: data.out(),
+        }
     }
 }
 
+/// The outcome of a transaction included in a block.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Receipt {
+    /// Whether the transaction had its effect. A failed transaction still pays the fee and bumps
+    /// the nonce of the sender.
+    pub success: bool,
+    /// The fee paid by the transaction.
+    pub fee: u64,
+    /// The sum of the fees paid by this and all the preceding transactions in the block.
+    pub cumulative_fees: u64,
+    /// The gas used by the transaction, see [`crate::txn_gas`].
+    pub gas_used: Gas,
+    /// The sum of the gas used by this and all the preceding transactions in the block.
+    pub cumulative_gas_used: Gas,
+    #[rlp(list)]
+    pub logs: Vec<Log>,
+}
+
+/// Builds the transactions trie for the given transactions. Returns the root and all the nodes of
+/// the trie.
+pub fn txns_trie(txns: &[TxnEnvelope]) -> (H256, BTreeMap<H256, Vec<u8>>) {
+    index_trie(txns)
+}
+
+/// Builds the receipts trie for the given receipts. Returns the root and all the nodes of the trie.
+pub fn receipts_trie(receipts: &[Receipt]) -> (H256, BTreeMap<H256, Vec<u8>>) {
+    index_trie(receipts)
+}
+
+/// Builds a trie that maps the RLP-encoded index of each item to the RLP-encoded item.
+fn index_trie<T: rlp::Encodable>(items: &[T]) -> (H256, BTreeMap<H256, Vec<u8>>) {
+    let map = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| (index_key(index as u64), rlp::encode(item)))
+        .collect();
+    let (root, change) = trie::build(&map);
+    (root, change.adds)
+}
+
+/// The trie key of the item at the given index.
+pub(crate) fn index_key(index: u64) -> Vec<u8> {
+    rlp::encode(&index)
+}
+
 pub fn keccak256(bytes: &[u8]) -> H256 {
     use sha3::{Digest, Keccak256};
     H256::from_slice(Keccak256::digest(bytes).as_ref())
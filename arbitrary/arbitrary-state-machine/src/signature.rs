@@ -0,0 +1,62 @@
+//! secp256k1 signatures that authorize transactions.
+//!
+//! An account address is the keccak256 hash of the uncompressed public key (without the `0x04`
+//! prefix). Unlike Ethereum, the hash is not truncated to 20 bytes since the state is keyed by
+//! `H256`.
+
+use crate::primitives::keccak256;
+use bigint::H256;
+use k256::ecdsa::{RecoveryId, SigningKey, VerifyingKey};
+use rlp_derive::{RlpDecodable, RlpEncodable};
+
+/// A recoverable ECDSA signature.
+///
+/// Transactions encode it flattened into their own RLP list, as the items `v`, `r` and `s`.
+#[derive(Clone, Default, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Signature {
+    /// The recovery id, either 0 or 1.
+    pub v: u8,
+    pub r: H256,
+    pub s: H256,
+}
+
+impl Signature {
+    /// Signs the given hash with the given secret key.
+    ///
+    /// Panics if the secret key is not a valid secp256k1 scalar.
+    pub fn sign(hash: H256, secret: &[u8; 32]) -> Signature {
+        let key = SigningKey::from_slice(secret).expect("invalid secret key");
+        let (sig, recid) = key
+            .sign_prehash_recoverable(&hash.0)
+            .expect("signing a 32 byte prehash cannot fail");
+        let bytes = sig.to_bytes();
+        Signature {
+            v: recid.to_byte(),
+            r: H256::from_slice(&bytes[..32]),
+            s: H256::from_slice(&bytes[32..]),
+        }
+    }
+
+    /// Recovers the address of the account that produced this signature over the given hash.
+    ///
+    /// Returns `None` if the signature is malformed.
+    pub fn recover(&self, hash: H256) -> Option<H256> {
+        let recid = RecoveryId::from_byte(self.v)?;
+        let sig = k256::ecdsa::Signature::from_scalars(self.r.0, self.s.0).ok()?;
+        let key = VerifyingKey::recover_from_prehash(&hash.0, &sig, recid).ok()?;
+        Some(address_of(&key))
+    }
+}
+
+/// Returns the address of the account controlled by the given secret key.
+///
+/// Panics if the secret key is not a valid secp256k1 scalar.
+pub fn address(secret: &[u8; 32]) -> H256 {
+    let key = SigningKey::from_slice(secret).expect("invalid secret key");
+    address_of(key.verifying_key())
+}
+
+fn address_of(key: &VerifyingKey) -> H256 {
+    let point = key.to_encoded_point(false);
+    keccak256(&point.as_bytes()[1..])
+}
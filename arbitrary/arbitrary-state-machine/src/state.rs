@@ -1,57 +1,570 @@
 //! This module describes the state of the blockchain.
 //!
-//! The state basically stores a mapping from an address to a balance.
+//! The state basically stores a mapping from an address to an account record. Alongside it, the
+//! state keeps the withdrawals trie that maps the RLP-encoded index of a withdrawal to the
+//! withdrawal, along with the number of withdrawals made so far.
+//!
+//! Every account has its own storage trie that maps keys to small values, see
+//! [`State::storage_get`]. The storage tries share the backend with the account trie. The storage
+//! trie of an account also holds its balances of the assets other than the native one, keyed by
+//! [`asset_key`]. The address of the admin that can mint them is kept in the storage of a system
+//! account, see [`State::admin`]. This way, the account trie only holds accounts, whatever the
+//! address.
+//!
+//! The state counts the trie accesses it makes, which is what transactions are charged gas for.
+//!
+//! A trie node missing from the backend doesn't make the state panic. The getters, like
+//! [`State::get_account`], return the hash of the node. Writes and commits treat the missing part
+//! of the trie as empty and carry on, remembering the hash, see [`State::missing_node`].
+//!
+//! Writes are not applied to the tries right away. They are kept in memory until
+//! [`State::commit`] is called, so that they can be undone with [`State::revert_to`] and so that
+//! the tries are only updated once per block.
 
+use crate::diff::{Diff, StateDiff};
+use crate::error::MissingNode;
+use crate::primitives::{index_key, keccak256, Withdrawal, NATIVE_ASSET};
 use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
-use bigint::H256;
+use bigint::{H256, U256};
+use core::cell::{Cell, RefCell};
+use rlp_derive::{RlpDecodable, RlpEncodable};
+
+/// An account record stored in the state trie.
+///
+/// Encoded as an RLP list `[nonce, balance, storage_root]`, similar to Ethereum accounts.
+#[derive(Clone, Debug, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Account {
+    /// The number of transactions sent from this account. The next transaction must carry exactly
+    /// this nonce.
+    pub nonce: u64,
+    pub balance: U256,
+    /// The root of the storage trie of this account. See [`State::storage_get`].
+    pub storage_root: H256,
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Account {
+            nonce: 0,
+            balance: U256::zero(),
+            storage_root: trie::EMPTY_TRIE_HASH,
+        }
+    }
+}
+
+impl Account {
+    /// Returns true if the account has a zero nonce, a zero balance and no storage, i.e. it is
+    /// indistinguishable from an absent account. Empty accounts are never stored in the state, see
+    /// [`State::put_account`].
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0 && self.balance.is_zero() && self.storage_root == trie::EMPTY_TRIE_HASH
+    }
+}
+
+/// The number of trie reads and writes made by a [`State`]. See [`State::trie_accesses`].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct TrieAccesses {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+impl TrieAccesses {
+    /// Returns the accesses made since the `earlier` snapshot was taken.
+    pub fn since(self, earlier: TrieAccesses) -> TrieAccesses {
+        TrieAccesses {
+            reads: self.reads - earlier.reads,
+            writes: self.writes - earlier.writes,
+        }
+    }
+}
+
+/// A point in the writes made to a [`State`] that they can be undone back to. See
+/// [`State::checkpoint`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    // The number of commits made before the checkpoint was taken, since a commit invalidates it.
+    commits: u64,
+    // The length of the journal at the checkpoint.
+    len: usize,
+}
+
+/// The trie a pending write goes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum TrieId {
+    Accounts,
+    Withdrawals,
+    /// The storage trie of the account with the given address.
+    Storage(H256),
+}
+
+/// The trie and the key in it of a pending write.
+type PendingKey = (TrieId, Vec<u8>);
 
 #[derive(Clone)]
 pub struct State<B = InMemoryBackend> {
+    // The roots as of the last commit.
     root: H256,
+    withdrawals_root: H256,
     backend: B,
+    // The writes made since the last commit by trie and key. `None` is a removal.
+    pending: BTreeMap<PendingKey, Option<Vec<u8>>>,
+    // The pending value of every key before each write since the last commit, or `None` if the key
+    // had no pending write, so that the writes can be undone.
+    journal: Vec<(PendingKey, Option<Option<Vec<u8>>>)>,
+    commits: u64,
+    // A cell since reads are made through a shared reference.
+    reads: Cell<u64>,
+    writes: u64,
+    // The values before the first change of everything changed since the recording of a diff was
+    // started, see `State::record_diff`. The new values are filled in when the diff is taken.
+    diff: Option<StateDiff>,
+    // The first node found missing from the backend.
+    missing: Cell<Option<H256>>,
 }
 
 impl<B: Backend> State<B> {
     /// Create a new empty state.
     pub fn empty(backend: B) -> Self {
-        Self::with_root(backend, trie::EMPTY_TRIE_HASH)
-    }
-
-    /// Creaates a new state at the given root.
-    pub fn with_root(backend: B, root: H256) -> Self {
-        State { root, backend }
-    }
-
-    /// Gets the balance for the specified address.
-    pub fn get(&self, key: H256) -> Option<u64> {
-        trie::get(self.root, &BackendWrapper(&self.backend), &key.0)
-            .unwrap()
-            .map(|bytes| {
-                let mut le = [0; 8];
-                le.copy_from_slice(&bytes);
-                u64::from_le_bytes(le)
-            })
-    }
-
-    /// Sets the balance for the specified address.
-    pub fn set(&mut self, key: H256, value: u64) {
-        let (root, change) = trie::insert(
-            self.root,
-            &BackendWrapper(&self.backend),
-            &key.0,
-            &value.to_le_bytes(),
-        )
-        .unwrap();
+        Self::with_roots(backend, trie::EMPTY_TRIE_HASH, trie::EMPTY_TRIE_HASH)
+    }
+
+    /// Creates a new state at the given roots of the account trie and the withdrawals trie.
+    pub fn with_roots(backend: B, root: H256, withdrawals_root: H256) -> Self {
+        State {
+            root,
+            withdrawals_root,
+            backend,
+            pending: BTreeMap::new(),
+            journal: Vec::new(),
+            commits: 0,
+            reads: Cell::new(0),
+            writes: 0,
+            diff: None,
+            missing: Cell::new(None),
+        }
+    }
+
+    /// Gets the account stored at the specified address, or the hash of the missing node if the
+    /// backend lacks a node on the path to the account.
+    ///
+    /// If the storage of the account was written since the last commit, the storage root is still
+    /// the one as of the last commit. It is updated by [`State::commit`].
+    pub fn get_account(&self, address: H256) -> Result<Option<Account>, MissingNode> {
+        self.get(TrieId::Accounts, &address.0)
+    }
+
+    /// Sets the account stored at the specified address.
+    pub fn set_account(&mut self, address: H256, account: &Account) {
+        self.record_account(address);
+        self.write(TrieId::Accounts, &address.0, Some(rlp::encode(account)));
+    }
+
+    /// Removes the account stored at the specified address. Does nothing if there is no such
+    /// account.
+    pub fn remove(&mut self, address: H256) {
+        self.record_account(address);
+        self.write(TrieId::Accounts, &address.0, None);
+    }
+
+    /// Stores the account at the specified address, or removes it if the account is empty.
+    ///
+    /// This is the account clearing rule, similar to EIP-161: an account that was touched and
+    /// ended up empty is deleted, so that an absent account and an empty one have the same root.
+    /// Note that an account that has sent a transaction is never empty since its nonce was bumped.
+    /// That is intended: deleting it would reset the nonce and allow replaying its transactions.
+    ///
+    /// An account with storage writes since the last commit is kept until the commit, which removes
+    /// it if its storage ended up empty too.
+    pub fn put_account(&mut self, address: H256, account: &Account) {
+        if account.is_empty() && !self.has_pending_storage(address) {
+            self.remove(address);
+        } else {
+            self.set_account(address, account);
+        }
+    }
+
+    /// Gets the balance of the given asset held by the specified address, or the hash of the missing
+    /// node if there is one.
+    pub fn balance(&self, address: H256, asset: H256) -> Result<U256, MissingNode> {
+        if asset == NATIVE_ASSET {
+            return Ok(self.get_account(address)?.unwrap_or_default().balance);
+        }
+        Ok(self
+            .read_slot(address, asset_key(asset), rlp::decode)?
+            .unwrap_or_default())
+    }
+
+    /// Sets the balance of the given asset held by the specified address. A zero balance of an
+    /// asset other than the native one is removed from the storage trie of the account.
+    pub fn set_balance(&mut self, address: H256, asset: H256, balance: U256) {
+        if asset == NATIVE_ASSET {
+            let mut account = self.lenient(self.get_account(address)).unwrap_or_default();
+            account.balance = balance;
+            self.put_account(address, &account);
+        } else {
+            self.record_asset(address, asset);
+            let balance = (!balance.is_zero()).then(|| rlp::encode(&balance));
+            self.write_slot(address, asset_key(asset), balance);
+        }
+    }
+
+    /// Returns the address of the admin, that is allowed to mint assets, if there is one.
+    ///
+    /// It is kept in the storage of the system account at `keccak256("admin")`, under that same
+    /// key. Nobody can sign for that address, so its storage cannot be written by a transaction.
+    pub fn admin(&self) -> Result<Option<H256>, MissingNode> {
+        self.read_slot(admin_key(), admin_key(), rlp::decode)
+    }
+
+    /// Sets the address of the admin. Only meant to be called for the genesis.
+    pub fn set_admin(&mut self, admin: H256) {
+        let admin = rlp::encode(&admin);
+        self.write_slot(admin_key(), admin_key(), Some(admin));
+    }
+
+    /// Gets the value stored under the given key in the storage of the specified account, or the
+    /// hash of the missing node if there is one.
+    pub fn storage_get(&self, address: H256, key: H256) -> Result<Option<Vec<u8>>, MissingNode> {
+        self.read_slot(address, storage_key(key), <[u8]>::to_vec)
+    }
+
+    /// Stores the value under the given key in the storage of the specified account. An empty
+    /// value removes the key.
+    ///
+    /// Like [`State::put_account`], removes the account if it ends up empty. That is only known
+    /// once the storage root is computed, so it is done by [`State::commit`].
+    pub fn storage_set(&mut self, address: H256, key: H256, value: &[u8]) {
+        self.record_storage(address, key);
+        let value = (!value.is_empty()).then(|| value.to_vec());
+        self.write_slot(address, storage_key(key), value);
+    }
+
+    /// Returns a checkpoint that the writes made from now on can be undone back to with
+    /// [`State::revert_to`]. Checkpoints can be nested. A commit invalidates all of them.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            commits: self.commits,
+            len: self.journal.len(),
+        }
+    }
+
+    /// Undoes the writes made since the given checkpoint was taken. The checkpoints taken after
+    /// it are invalidated. The trie accesses made are still counted.
+    ///
+    /// Panics if there was a commit since the checkpoint was taken.
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) {
+        assert_eq!(
+            checkpoint.commits, self.commits,
+            "the checkpoint was invalidated by a commit"
+        );
+        for (key, previous) in self.journal.drain(checkpoint.len..).rev() {
+            match previous {
+                Some(value) => self.pending.insert(key, value),
+                None => self.pending.remove(&key),
+            };
+        }
+    }
+
+    /// Applies the writes made since the last commit to the tries and updates the roots.
+    ///
+    /// Every written key is inserted into its trie once, whatever the number of writes to it, and
+    /// every modified trie node is hashed once. The storage tries are updated first, then the
+    /// storage roots of their accounts, which are removed if they ended up empty.
+    pub fn commit(&mut self) {
+        self.commits += 1;
+        self.journal.clear();
+        let mut accounts = BTreeMap::new();
+        let mut withdrawals = BTreeMap::new();
+        let mut storage: BTreeMap<H256, Vec<_>> = BTreeMap::new();
+        for ((trie, key), value) in core::mem::take(&mut self.pending) {
+            match trie {
+                TrieId::Accounts => {
+                    accounts.insert(key, value);
+                }
+                TrieId::Withdrawals => {
+                    withdrawals.insert(key, value);
+                }
+                TrieId::Storage(address) => storage.entry(address).or_default().push((key, value)),
+            }
+        }
+
+        for (address, writes) in storage {
+            let mut account = match accounts.get(&address.0[..]) {
+                Some(account) => account.as_deref().map(rlp::decode).unwrap_or_default(),
+                None => self.committed_account(address),
+            };
+            account.storage_root = self.apply(account.storage_root, writes);
+            let account = (!account.is_empty()).then(|| rlp::encode(&account));
+            accounts.insert(address.0.to_vec(), account);
+        }
+        self.root = self.apply(self.root, accounts);
+        self.withdrawals_root = self.apply(self.withdrawals_root, withdrawals);
+    }
+
+    /// Starts recording the changes made to the state, discarding the ones recorded so far. See
+    /// [`State::take_diff`].
+    pub fn record_diff(&mut self) {
+        self.diff = Some(StateDiff::default());
+    }
+
+    /// Stops recording the changes made to the state and returns the ones made since
+    /// [`State::record_diff`] was called, or an empty diff if it wasn't.
+    ///
+    /// The reads made for recording the diff are not counted in [`State::trie_accesses`], so that
+    /// recording doesn't change the gas used by transactions.
+    pub fn take_diff(&mut self) -> StateDiff {
+        let mut diff = self.diff.take().unwrap_or_default();
+        self.uncounted(|state| {
+            for (address, entry) in diff.accounts.iter_mut() {
+                entry.new = state.lenient(state.get_account(*address));
+            }
+            for ((address, asset), entry) in diff.assets.iter_mut() {
+                entry.new = state.lenient(state.balance(*address, *asset));
+            }
+            for ((address, key), entry) in diff.storage.iter_mut() {
+                entry.new = state.lenient(state.storage_get(*address, *key));
+            }
+        });
+        diff.accounts.retain(|_, entry| entry.old != entry.new);
+        diff.assets.retain(|_, entry| entry.old != entry.new);
+        diff.storage.retain(|_, entry| entry.old != entry.new);
+        diff
+    }
+
+    fn record_account(&mut self, address: H256) {
+        if let Some(diff) = &self.diff {
+            if !diff.accounts.contains_key(&address) {
+                let old = self.uncounted(|state| state.lenient(state.get_account(address)));
+                let entry = Diff {
+                    new: old.clone(),
+                    old,
+                };
+                self.diff.as_mut().unwrap().accounts.insert(address, entry);
+            }
+        }
+    }
+
+    fn record_asset(&mut self, address: H256, asset: H256) {
+        if let Some(diff) = &self.diff {
+            if !diff.assets.contains_key(&(address, asset)) {
+                let old = self.uncounted(|state| state.lenient(state.balance(address, asset)));
+                let entry = Diff { old, new: old };
+                self.diff
+                    .as_mut()
+                    .unwrap()
+                    .assets
+                    .insert((address, asset), entry);
+            }
+        }
+    }
+
+    fn record_storage(&mut self, address: H256, key: H256) {
+        if let Some(diff) = &self.diff {
+            if !diff.storage.contains_key(&(address, key)) {
+                let old = self.uncounted(|state| state.lenient(state.storage_get(address, key)));
+                let entry = Diff {
+                    new: old.clone(),
+                    old,
+                };
+                self.diff
+                    .as_mut()
+                    .unwrap()
+                    .storage
+                    .insert((address, key), entry);
+            }
+        }
+    }
+
+    /// Runs the given lookups without counting their trie reads.
+    fn uncounted<T>(&self, f: impl FnOnce(&Self) -> T) -> T {
+        let reads = self.reads.get();
+        let result = f(self);
+        self.reads.set(reads);
+        result
+    }
+
+    /// Returns the result of a lookup, or the default value if a node is missing, remembering its
+    /// hash, see [`State::missing_node`]. This is how the state transition function reads the
+    /// state, so that it finds all the missing nodes of a block in one execution.
+    pub(crate) fn lenient<T: Default>(&self, result: Result<T, MissingNode>) -> T {
+        result.unwrap_or_else(|MissingNode(hash)| {
+            self.note_missing(hash);
+            T::default()
+        })
+    }
+
+    fn note_missing(&self, hash: H256) {
+        if self.missing.get().is_none() {
+            self.missing.set(Some(hash));
+        }
+    }
+
+    /// Reads and decodes the value under the given key in the account or the withdrawals trie.
+    fn get<T: rlp::Decodable>(&self, trie: TrieId, key: &[u8]) -> Result<Option<T>, MissingNode> {
+        let root = match trie {
+            TrieId::Withdrawals => self.withdrawals_root,
+            _ => self.root,
+        };
+        self.read(trie, root, key, rlp::decode)
+    }
+
+    /// Reads the value under the given key in the given trie, whose root as of the last commit is
+    /// `root`. A pending write takes precedence over the trie.
+    fn read<T>(
+        &self,
+        trie: TrieId,
+        root: H256,
+        key: &[u8],
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, MissingNode> {
+        self.reads.set(self.reads.get() + 1);
+        match self.pending.get(&(trie, key.to_vec())) {
+            Some(value) => Ok(value.as_deref().map(f)),
+            None => Ok(trie::get(root, &BackendWrapper(&self.backend), key)?.map(f)),
+        }
+    }
+
+    /// Makes a pending write of the value under the given key in the given trie. `None` removes the
+    /// key.
+    fn write(&mut self, trie: TrieId, key: &[u8], value: Option<Vec<u8>>) {
+        self.writes += 1;
+        let key = (trie, key.to_vec());
+        let previous = self.pending.insert(key.clone(), value);
+        self.journal.push((key, previous));
+    }
+
+    /// Reads the value under the given slot, which is an already hashed key, in the storage trie of
+    /// the given account.
+    fn read_slot<T>(
+        &self,
+        address: H256,
+        slot: H256,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>, MissingNode> {
+        let storage_root = self.get_account(address)?.unwrap_or_default().storage_root;
+        self.read(TrieId::Storage(address), storage_root, &slot.0, f)
+    }
+
+    /// Makes a pending write of the value under the given slot in the storage trie of the given
+    /// account. `None` removes the slot.
+    fn write_slot(&mut self, address: H256, slot: H256, value: Option<Vec<u8>>) {
+        // The account is written as is to create it if it's absent. Its storage root is updated on
+        // commit.
+        let account = self.lenient(self.get_account(address)).unwrap_or_default();
+        self.write(TrieId::Storage(address), &slot.0, value);
+        self.set_account(address, &account);
+    }
+
+    fn has_pending_storage(&self, address: H256) -> bool {
+        let start = (TrieId::Storage(address), Vec::new());
+        self.pending
+            .range(start..)
+            .next()
+            .is_some_and(|((trie, _), _)| *trie == TrieId::Storage(address))
+    }
+
+    /// Returns the account at the given address as of the last commit.
+    fn committed_account(&self, address: H256) -> Account {
+        let account = trie::get(self.root, &WriteWrapper(&self.backend), &address.0)
+            .map(|account| account.map(rlp::decode))
+            .map_err(MissingNode::from);
+        self.lenient(account).unwrap_or_default()
+    }
+
+    /// Applies the given writes to the trie with the given root. Returns the new root.
+    ///
+    /// The writes go through a [`trie::TrieCache`], so every modified node is hashed once rather
+    /// than once per write under it.
+    fn apply(
+        &mut self,
+        root: H256,
+        writes: impl IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    ) -> H256 {
+        let mut cache = trie::TrieCache::new(root);
+        let db = WriteWrapper(&self.backend);
+        for (key, value) in writes {
+            let result = match value {
+                Some(value) => cache.insert(&db, &key, &value),
+                None => cache.delete(&db, &key),
+            };
+            // The write is skipped, the cache is left as it was.
+            self.lenient(result.map_err(MissingNode::from));
+        }
+        let (root, change) = cache.commit();
         self.backend.apply_changes(change.adds, change.removes);
-        self.root = root;
+        root
+    }
+
+    /// Records the withdrawal in the withdrawals trie. Returns the index of the withdrawal.
+    ///
+    /// The index is the number of withdrawals made so far, which is kept in the withdrawals trie
+    /// too and is bumped.
+    pub fn push_withdrawal(&mut self, withdrawal: &Withdrawal) -> u64 {
+        let index: u64 = self
+            .lenient(self.get(TrieId::Withdrawals, WITHDRAWAL_COUNT_KEY))
+            .unwrap_or_default();
+        let withdrawal = rlp::encode(withdrawal);
+        self.write(TrieId::Withdrawals, &index_key(index), Some(withdrawal));
+        let count = rlp::encode(&(index + 1));
+        self.write(TrieId::Withdrawals, WITHDRAWAL_COUNT_KEY, Some(count));
+        index
+    }
+
+    /// Gets the withdrawal with the given index, or the hash of the missing node if there is one.
+    pub fn withdrawal(&self, index: u64) -> Result<Option<Withdrawal>, MissingNode> {
+        self.get(TrieId::Withdrawals, &index_key(index))
+    }
+
+    /// Returns the proof of the withdrawal with the given index against the withdrawals root, or
+    /// `None` if there is no such withdrawal. Withdrawals made since the last commit have no proof
+    /// yet. A node missing from the backend is returned as an error rather than taken as absent.
+    ///
+    /// The proof is the list of the trie nodes on the path from the root to the withdrawal, in that
+    /// order. The withdrawals root in turn is committed to by the output root, see
+    /// [`crate::output_root`].
+    pub fn withdrawal_proof(&self, index: u64) -> Result<Option<Vec<Vec<u8>>>, MissingNode> {
+        let recorder = ProofRecorder {
+            backend: &self.backend,
+            nodes: RefCell::new(Vec::new()),
+        };
+        if trie::get(self.withdrawals_root, &recorder, &index_key(index))?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(recorder.nodes.into_inner()))
+    }
+
+    /// Returns the number of trie reads and writes made by this state so far. Proofs are not
+    /// counted.
+    pub fn trie_accesses(&self) -> TrieAccesses {
+        TrieAccesses {
+            reads: self.reads.get(),
+            writes: self.writes,
+        }
+    }
+
+    /// Returns the first trie node that was found missing from the backend by the methods that don't
+    /// return a `Result`, like [`State::set_balance`] and [`State::commit`], or by
+    /// [`crate::execute`], if any.
+    ///
+    /// Those treat the missing part of a trie as empty, and commits skip the writes under it, so the
+    /// state is wrong from then on and must be discarded. They carry on so that all the nodes a block
+    /// needs can be found in one execution, see [`crate::WitnessBackend`]. Writing to a state
+    /// without checking this afterwards may thus silently give wrong results.
+    pub fn missing_node(&self) -> Option<H256> {
+        self.missing.get()
     }
 
-    /// Returns the root of the state.
+    /// Returns the root of the account trie as of the last commit.
     pub fn root(&self) -> H256 {
         self.root
     }
 
+    /// Returns the root of the withdrawals trie as of the last commit.
+    pub fn withdrawals_root(&self) -> H256 {
+        self.withdrawals_root
+    }
+
     /// Consume the state and return the backend.
     pub fn into_backend(self) -> B {
         self.backend
@@ -63,7 +576,34 @@ impl<B: Backend> State<B> {
     }
 }
 
-struct BackendWrapper<'a>(&'a dyn Backend);
+/// Returns the key of the balance of the given asset in the storage trie of its holder.
+///
+/// The key is `keccak256("asset" ++ asset)`. The keys written by [`State::storage_set`] are hashes
+/// of 32 bytes, so the longer preimage keeps the two apart.
+pub fn asset_key(asset: H256) -> H256 {
+    let mut preimage = Vec::with_capacity(5 + 32);
+    preimage.extend_from_slice(b"asset");
+    preimage.extend_from_slice(&asset.0);
+    keccak256(&preimage)
+}
+
+/// Returns the key of the given storage key in a storage trie. Hashing the keys keeps the storage
+/// tries balanced, whatever keys are chosen.
+fn storage_key(key: H256) -> H256 {
+    keccak256(&key.0)
+}
+
+/// The key of the number of withdrawals in the withdrawals trie. The keys of the withdrawals are
+/// RLP-encoded integers, and no such encoding is 5 bytes long and starts with a byte below 0x80.
+const WITHDRAWAL_COUNT_KEY: &[u8] = b"count";
+
+/// Returns the address of the system account holding the admin, which is also the key it is stored
+/// under. See [`State::admin`].
+pub(crate) fn admin_key() -> H256 {
+    keccak256(b"admin")
+}
+
+pub(crate) struct BackendWrapper<'a>(pub(crate) &'a dyn Backend);
 
 impl trie::DatabaseHandle for BackendWrapper<'_> {
     fn get(&self, key: H256) -> Option<&[u8]> {
@@ -71,13 +611,47 @@ impl trie::DatabaseHandle for BackendWrapper<'_> {
     }
 }
 
+/// Gets the nodes with [`Backend::get_for_write`].
+struct WriteWrapper<'a>(&'a dyn Backend);
+
+impl trie::DatabaseHandle for WriteWrapper<'_> {
+    fn get(&self, key: H256) -> Option<&[u8]> {
+        self.0.get_for_write(key)
+    }
+}
+
+/// Records the nodes accessed by a trie lookup, which constitute the proof of the looked up value.
+struct ProofRecorder<'a> {
+    backend: &'a dyn Backend,
+    nodes: RefCell<Vec<Vec<u8>>>,
+}
+
+impl trie::DatabaseHandle for ProofRecorder<'_> {
+    fn get(&self, key: H256) -> Option<&[u8]> {
+        let node = self.backend.get(key);
+        if let Some(node) = node {
+            self.nodes.borrow_mut().push(node.to_vec());
+        }
+        node
+    }
+}
+
 /// An abstraction for a trie backend. Expected to keep track of the nodes.
 pub trait Backend {
     /// Get the given nodes from the backend, or `None` if not present.
     fn get(&self, key: H256) -> Option<&[u8]>;
-    /// Apply the given change set to the backend. Technically, a confirming implementation does not
-    /// have to remove the nodes in `removes`. The order of processing is `adds` first and then
+    /// Get the given node to apply writes to its trie, see [`State::commit`]. Backends that care
+    /// about why a node is needed can tell it apart from [`Backend::get`] this way.
+    fn get_for_write(&self, key: H256) -> Option<&[u8]> {
+        self.get(key)
+    }
+    /// Apply the given change set to the backend. The order of processing is `adds` first and then
     /// `removes`.
+    ///
+    /// The removes are advisory: an append-only backend may ignore them. The tries of the state
+    /// share the backend, and identical subtries in different tries are the same nodes, so a node
+    /// removed from one trie may still be referenced by another one. A backend that does remove
+    /// nodes has to count references.
     fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, removes: BTreeSet<H256>) {
         // provided implementation since as shown by the preimage oracle not all backends need
         // to keep track of changes.
@@ -85,8 +659,8 @@ pub trait Backend {
     }
 }
 
-/// A simple trie backend implementation backed by a b-tree map.
-#[derive(Clone)]
+/// A simple trie backend implementation backed by a b-tree map. It never removes nodes.
+#[derive(Clone, Default)]
 pub struct InMemoryBackend {
     nodes: BTreeMap<H256, Vec<u8>>,
 }
@@ -104,12 +678,9 @@ impl Backend for InMemoryBackend {
     fn get(&self, key: H256) -> Option<&[u8]> {
         self.nodes.get(&key).map(|v| v.as_ref())
     }
-    fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, removes: BTreeSet<H256>) {
+    fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, _removes: BTreeSet<H256>) {
         for (key, value) in adds {
             self.nodes.insert(key, value);
         }
-        for key in removes {
-            self.nodes.remove(&key);
-        }
     }
 }
@@ -0,0 +1,245 @@
+//! Witnesses: the trie nodes needed to execute a block on top of its parent state, without the rest
+//! of the state.
+//!
+//! A prover records the witness by executing the block over a [`RecordingBackend`], then the guest
+//! executes it again with the witness as its backend. Without access to the whole state, the
+//! witness can instead be completed step by step with a [`WitnessBackend`].
+
+use crate::primitives::keccak256;
+use crate::state::Backend;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use bigint::H256;
+use rlp::{Decodable, DecoderError, Encodable, RlpStream, UntrustedRlp};
+
+/// A set of trie nodes, keyed by their hash.
+///
+/// Encoded as an RLP list of the nodes, in the order of their hashes. The hashes are not encoded
+/// since they are recomputed when decoding.
+///
+/// As a [`Backend`], it serves the nodes it contains and keeps the nodes added by commits, so that
+/// the state can be read after a commit.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Witness {
+    nodes: BTreeMap<H256, Vec<u8>>,
+}
+
+impl Witness {
+    /// Creates an empty witness.
+    pub fn new() -> Witness {
+        Witness::default()
+    }
+
+    /// Adds the node to the witness.
+    pub fn insert(&mut self, node: Vec<u8>) {
+        self.nodes.insert(keccak256(&node), node);
+    }
+
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns true if there are no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the nodes by hash.
+    pub fn nodes(&self) -> &BTreeMap<H256, Vec<u8>> {
+        &self.nodes
+    }
+
+    /// Returns the nodes by hash.
+    pub fn into_nodes(self) -> BTreeMap<H256, Vec<u8>> {
+        self.nodes
+    }
+
+    /// Writes every node to a file named after its hash in the given directory, which is the
+    /// layout of the preimages served to the guest by the preimage oracle.
+    #[cfg(feature = "std")]
+    pub fn write_preimages(&self, dir: &std::path::Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        for (hash, node) in &self.nodes {
+            std::fs::write(dir.join(std::format!("0x{:?}", hash)), node)?;
+        }
+        Ok(())
+    }
+}
+
+impl Extend<Vec<u8>> for Witness {
+    fn extend<I: IntoIterator<Item = Vec<u8>>>(&mut self, nodes: I) {
+        for node in nodes {
+            self.insert(node);
+        }
+    }
+}
+
+impl FromIterator<Vec<u8>> for Witness {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(nodes: I) -> Witness {
+        let mut witness = Witness::new();
+        witness.extend(nodes);
+        witness
+    }
+}
+
+impl Encodable for Witness {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(self.nodes.len());
+        for node in self.nodes.values() {
+            s.append(node);
+        }
+    }
+}
+
+impl Decodable for Witness {
+    fn decode(rlp: &UntrustedRlp) -> Result<Self, DecoderError> {
+        Ok(rlp.as_list::<Vec<u8>>()?.into_iter().collect())
+    }
+}
+
+impl Backend for Witness {
+    fn get(&self, key: H256) -> Option<&[u8]> {
+        self.nodes.get(&key).map(|node| node.as_ref())
+    }
+
+    fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, _removes: BTreeSet<H256>) {
+        self.nodes.extend(adds);
+    }
+}
+
+/// A backend serving the nodes of a witness that reports the nodes missing from it.
+///
+/// The state carries on when a node is missing, see [`crate::State::missing_node`], so executing a
+/// block over this backend reports every missing node met along the way in one pass. Since the
+/// values that were missing were taken as absent, the execution may take a different path once
+/// they are added to the witness, which may need more nodes. The witness is complete once an
+/// execution reports no missing node.
+pub struct WitnessBackend {
+    witness: Witness,
+    // RefCell is required here because the backend is read through a shared reference.
+    missing: core::cell::RefCell<BTreeSet<H256>>,
+}
+
+impl WitnessBackend {
+    /// Serves the nodes of the given witness, with nothing reported missing yet.
+    pub fn new(witness: Witness) -> Self {
+        WitnessBackend {
+            witness,
+            missing: Default::default(),
+        }
+    }
+
+    /// Returns the hashes of the nodes that were requested but are not in the witness.
+    pub fn missing(&self) -> BTreeSet<H256> {
+        self.missing.borrow().clone()
+    }
+}
+
+impl Backend for WitnessBackend {
+    fn get(&self, key: H256) -> Option<&[u8]> {
+        let node = self.witness.get(key);
+        if node.is_none() {
+            self.missing.borrow_mut().insert(key);
+        }
+        node
+    }
+
+    fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, removes: BTreeSet<H256>) {
+        self.witness.apply_changes(adds, removes);
+    }
+}
+
+/// A backend that records the nodes read from the wrapped backend, which make up the witness of
+/// what was executed over it.
+///
+/// The nodes are told apart by why they were needed: to read values, or only to apply the writes
+/// when the state is committed. The nodes added by the commits are not recorded, since executing
+/// the same thing again recreates them.
+#[cfg(feature = "std")]
+pub struct RecordingBackend<B> {
+    inner: B,
+    // RefCell is required here because the backend is read through a shared reference.
+    reads: core::cell::RefCell<Witness>,
+    writes: core::cell::RefCell<Witness>,
+    added: BTreeSet<H256>,
+}
+
+#[cfg(feature = "std")]
+impl<B: Backend> RecordingBackend<B> {
+    /// Wraps the given backend, with nothing recorded yet.
+    pub fn new(inner: B) -> Self {
+        RecordingBackend {
+            inner,
+            reads: Default::default(),
+            writes: Default::default(),
+            added: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the nodes needed to read values.
+    pub fn reads(&self) -> Witness {
+        self.reads.borrow().clone()
+    }
+
+    /// Returns the nodes that were only needed to apply writes, not to read values.
+    pub fn writes(&self) -> Witness {
+        self.writes.borrow().clone()
+    }
+
+    /// Returns all the recorded nodes.
+    pub fn witness(&self) -> Witness {
+        let mut witness = self.reads();
+        witness.nodes.extend(self.writes.borrow().nodes.clone());
+        witness
+    }
+
+    /// Returns the wrapped backend and all the recorded nodes.
+    pub fn into_inner(self) -> (B, Witness) {
+        let witness = self.witness();
+        (self.inner, witness)
+    }
+
+    fn record(&self, key: H256, write: bool) -> Option<&[u8]> {
+        // Pass the hint on, the wrapped backend may care about it too.
+        let node = if write {
+            self.inner.get_for_write(key)?
+        } else {
+            self.inner.get(key)?
+        };
+        if self.added.contains(&key) || self.reads.borrow().nodes.contains_key(&key) {
+            return Some(node);
+        }
+        if write {
+            self.writes.borrow_mut().nodes.insert(key, node.to_vec());
+        } else {
+            // A node needed for both is only recorded as read.
+            let recorded = self.writes.borrow_mut().nodes.remove(&key);
+            let recorded = recorded.unwrap_or_else(|| node.to_vec());
+            self.reads.borrow_mut().nodes.insert(key, recorded);
+        }
+        Some(node)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<B: Backend> Backend for RecordingBackend<B> {
+    fn get(&self, key: H256) -> Option<&[u8]> {
+        self.record(key, false)
+    }
+
+    fn get_for_write(&self, key: H256) -> Option<&[u8]> {
+        self.record(key, true)
+    }
+
+    fn apply_changes(&mut self, adds: BTreeMap<H256, Vec<u8>>, removes: BTreeSet<H256>) {
+        // A node that was in the backend before is still needed, even if it is added again.
+        let recorded = |key: &H256| {
+            self.reads.borrow().nodes.contains_key(key)
+                || self.writes.borrow().nodes.contains_key(key)
+        };
+        let added: Vec<H256> = adds.keys().filter(|key| !recorded(key)).copied().collect();
+        self.added.extend(added);
+        self.inner.apply_changes(adds, removes);
+    }
+}
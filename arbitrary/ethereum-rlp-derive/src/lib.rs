@@ -0,0 +1,351 @@
+//! Derive macros for the `Encodable` and `Decodable` traits of `ethereum-rlp`.
+//!
+//! A struct is encoded as an RLP list of its fields in their declaration order:
+//!
+//! ```ignore
+//! #[derive(RlpEncodable, RlpDecodable)]
+//! struct Output {
+//!     to: H256,
+//!     value: U256,
+//! }
+//! ```
+//!
+//! The derived `Decodable` rejects lists with a wrong number of items. The generated code refers to
+//! the `rlp` crate, so the crate using the derives must depend on `ethereum-rlp` under that name. It
+//! doesn't use `std`, so it works in `no_std` crates.
+//!
+//! The encoding can be tweaked with `#[rlp(...)]` attributes.
+//!
+//! On the struct:
+//!
+//! - `transparent`: the struct must have exactly one field and is encoded as that field, not as a
+//!   list.
+//! - `trailing`: the `Option` fields at the end of the struct are left out of the list if they are
+//!   `None`. Since the items are identified by their position, a `None` can't be followed by a
+//!   `Some`, encoding such a value panics.
+//!
+//! On a field:
+//!
+//! - `list`: the field is a `Vec` of items that are encoded as a nested list.
+//! - `flatten`: the items of the field, which must also derive the traits, are spliced into the
+//!   list instead of being nested. The flattened struct must not have trailing fields.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Index, Member, Type};
+
+/// Derives `rlp::Encodable` and `rlp::EncodableFields`. See the crate docs for the attributes.
+#[proc_macro_derive(RlpEncodable, attributes(rlp))]
+pub fn derive_encodable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    Layout::parse(&input)
+        .map(|layout| layout.encodable(&input))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `rlp::Decodable` and `rlp::DecodableFields`. See the crate docs for the attributes.
+#[proc_macro_derive(RlpDecodable, attributes(rlp))]
+pub fn derive_decodable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    Layout::parse(&input)
+        .map(|layout| layout.decodable(&input))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// How a field is encoded.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// As a single item.
+    Value,
+    /// As a nested list of items.
+    List,
+    /// As the items of the field spliced into the list.
+    Flatten,
+    /// As a single item if it is `Some`, or not at all.
+    Trailing,
+}
+
+struct Field {
+    member: Member,
+    ty: Type,
+    kind: Kind,
+}
+
+struct Layout {
+    transparent: bool,
+    fields: Vec<Field>,
+}
+
+impl Layout {
+    fn parse(input: &DeriveInput) -> syn::Result<Layout> {
+        let data = match &input.data {
+            Data::Struct(data) => data,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "RLP derives only support structs",
+                ))
+            }
+        };
+
+        let mut transparent = false;
+        let mut trailing = false;
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("rlp"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("transparent") {
+                    transparent = true;
+                    Ok(())
+                } else if meta.path.is_ident("trailing") {
+                    trailing = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `transparent` or `trailing`"))
+                }
+            })?;
+        }
+
+        let mut fields = Vec::new();
+        for (index, field) in data.fields.iter().enumerate() {
+            let mut kind = Kind::Value;
+            for attr in field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("rlp"))
+            {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("list") {
+                        kind = Kind::List;
+                        Ok(())
+                    } else if meta.path.is_ident("flatten") {
+                        kind = Kind::Flatten;
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `list` or `flatten`"))
+                    }
+                })?;
+            }
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(Index::from(index)),
+            };
+            fields.push(Field {
+                member,
+                ty: field.ty.clone(),
+                kind,
+            });
+        }
+
+        if transparent && (fields.len() != 1 || trailing) {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "a transparent struct must have exactly one field and no trailing fields",
+            ));
+        }
+        if trailing {
+            // All the `Option` fields at the end are trailing.
+            for field in fields.iter_mut().rev() {
+                if !is_option(&field.ty) || field.kind != Kind::Value {
+                    break;
+                }
+                field.kind = Kind::Trailing;
+            }
+            if fields.iter().all(|field| field.kind != Kind::Trailing) {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "`trailing` requires the struct to end with `Option` fields",
+                ));
+            }
+        }
+
+        Ok(Layout {
+            transparent,
+            fields,
+        })
+    }
+
+    fn encodable(&self, input: &DeriveInput) -> TokenStream2 {
+        let name = &input.ident;
+        let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+        if self.transparent {
+            let member = &self.fields[0].member;
+            return quote! {
+                impl #impl_generics ::rlp::Encodable for #name #ty_generics #where_clause {
+                    fn rlp_append(&self, s: &mut ::rlp::RlpStream) {
+                        ::rlp::Encodable::rlp_append(&self.#member, s);
+                    }
+                }
+            };
+        }
+
+        let mut counts = Vec::new();
+        let mut appends = Vec::new();
+        // The number of trailing fields that are present, i.e. the position of the last `Some`.
+        let mut present = Vec::new();
+        let mut trailing = 0usize;
+        for field in &self.fields {
+            let member = &field.member;
+            match field.kind {
+                Kind::Value => {
+                    counts.push(quote!(1));
+                    appends.push(quote!(s.append(&self.#member);));
+                }
+                Kind::List => {
+                    counts.push(quote!(1));
+                    appends.push(quote!(s.append_list(&self.#member);));
+                }
+                Kind::Flatten => {
+                    counts.push(quote!(::rlp::EncodableFields::field_count(&self.#member)));
+                    appends.push(quote!(::rlp::EncodableFields::append_fields(&self.#member, s);));
+                }
+                Kind::Trailing => {
+                    trailing += 1;
+                    present.push(quote! {
+                        if self.#member.is_some() {
+                            present = #trailing;
+                        }
+                    });
+                    let message = format!(
+                        "`{}` is None but a later trailing field is Some",
+                        quote!(#member)
+                    );
+                    appends.push(quote! {
+                        if #trailing <= present {
+                            s.append(self.#member.as_ref().expect(#message));
+                        }
+                    });
+                }
+            }
+        }
+
+        // Computed as a block in both methods, so no inherent item is added to the type.
+        let present = if trailing > 0 {
+            counts.push(quote!(present));
+            quote! {
+                let mut present = 0;
+                #(#present)*
+            }
+        } else {
+            quote!()
+        };
+
+        quote! {
+            impl #impl_generics ::rlp::EncodableFields for #name #ty_generics #where_clause {
+                fn field_count(&self) -> usize {
+                    #present
+                    0 #(+ #counts)*
+                }
+
+                fn append_fields(&self, s: &mut ::rlp::RlpStream) {
+                    #present
+                    #(#appends)*
+                }
+            }
+
+            impl #impl_generics ::rlp::Encodable for #name #ty_generics #where_clause {
+                fn rlp_append(&self, s: &mut ::rlp::RlpStream) {
+                    s.begin_list(::rlp::EncodableFields::field_count(self));
+                    ::rlp::EncodableFields::append_fields(self, s);
+                }
+            }
+        }
+    }
+
+    fn decodable(&self, input: &DeriveInput) -> TokenStream2 {
+        let name = &input.ident;
+        let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+        if self.transparent {
+            let member = &self.fields[0].member;
+            return quote! {
+                impl #impl_generics ::rlp::Decodable for #name #ty_generics #where_clause {
+                    fn decode(rlp: &::rlp::UntrustedRlp) -> Result<Self, ::rlp::DecoderError> {
+                        Ok(#name {
+                            #member: ::rlp::Decodable::decode(rlp)?,
+                        })
+                    }
+                }
+            };
+        }
+
+        // The index of every field is the offset plus the counts of all the fields before it.
+        let mut required = Vec::new();
+        let mut trailing = 0usize;
+        let mut decodes = Vec::new();
+        let mut inits = Vec::new();
+        for (position, field) in self.fields.iter().enumerate() {
+            let member = &field.member;
+            let ty = &field.ty;
+            let var = format_ident!("field{}", position);
+            let index = quote!(offset #(+ #required)* + #trailing);
+            decodes.push(match field.kind {
+                Kind::Value => quote!(let #var = rlp.val_at(#index)?;),
+                Kind::List => quote!(let #var = rlp.list_at(#index)?;),
+                Kind::Flatten => {
+                    quote!(let #var = <#ty as ::rlp::DecodableFields>::decode_fields(rlp, #index)?;)
+                }
+                Kind::Trailing => quote! {
+                    let #var = if #index < rlp.item_count()? {
+                        Some(rlp.val_at(#index)?)
+                    } else {
+                        None
+                    };
+                },
+            });
+            match field.kind {
+                Kind::Value | Kind::List => required.push(quote!(1)),
+                Kind::Flatten => {
+                    required.push(quote!(<#ty as ::rlp::DecodableFields>::FIELD_COUNT))
+                }
+                Kind::Trailing => trailing += 1,
+            }
+            inits.push(quote!(#member: #var));
+        }
+        // A struct expression with `0: ...` members also builds tuple structs.
+        let construct = quote!(#name { #(#inits),* });
+
+        quote! {
+            impl #impl_generics ::rlp::DecodableFields for #name #ty_generics #where_clause {
+                const FIELD_COUNT: usize = 0 #(+ #required)*;
+
+                fn decode_fields(
+                    rlp: &::rlp::UntrustedRlp,
+                    offset: usize,
+                ) -> Result<Self, ::rlp::DecoderError> {
+                    #(#decodes)*
+                    Ok(#construct)
+                }
+            }
+
+            impl #impl_generics ::rlp::Decodable for #name #ty_generics #where_clause {
+                fn decode(rlp: &::rlp::UntrustedRlp) -> Result<Self, ::rlp::DecoderError> {
+                    let count = rlp.item_count()?;
+                    let required = <Self as ::rlp::DecodableFields>::FIELD_COUNT;
+                    if count < required || count > required + #trailing {
+                        return Err(::rlp::DecoderError::RlpIncorrectListLen);
+                    }
+                    <Self as ::rlp::DecodableFields>::decode_fields(rlp, 0)
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if the type is spelled as an `Option`.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
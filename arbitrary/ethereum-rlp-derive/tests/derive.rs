@@ -0,0 +1,124 @@
+use rlp::{DecoderError, RlpStream, UntrustedRlp};
+use rlp_derive::{RlpDecodable, RlpEncodable};
+
+#[derive(Debug, PartialEq, RlpEncodable, RlpDecodable)]
+struct Point {
+    x: u64,
+    y: u64,
+}
+
+#[derive(Debug, PartialEq, RlpEncodable, RlpDecodable)]
+struct Pair(u64, Vec<u8>);
+
+#[derive(Debug, PartialEq, RlpEncodable, RlpDecodable)]
+#[rlp(transparent)]
+struct Id(u64);
+
+#[derive(Debug, PartialEq, RlpEncodable, RlpDecodable)]
+struct Shape {
+    id: Id,
+    #[rlp(list)]
+    points: Vec<Point>,
+    #[rlp(flatten)]
+    origin: Point,
+}
+
+#[derive(Debug, PartialEq, RlpEncodable, RlpDecodable)]
+#[rlp(trailing)]
+struct Versioned {
+    name: String,
+    extra: Option<u64>,
+    more: Option<u64>,
+}
+
+fn decode<T: rlp::Decodable>(bytes: &[u8]) -> Result<T, DecoderError> {
+    UntrustedRlp::new(bytes).as_val()
+}
+
+#[test]
+fn struct_is_a_list_of_its_fields() {
+    let point = Point { x: 1, y: 2 };
+    let mut s = RlpStream::new_list(2);
+    s.append(&1u64).append(&2u64);
+    let expected = s.out();
+    assert_eq!(rlp::encode(&point), expected);
+    assert_eq!(decode::<Point>(&expected).unwrap(), point);
+
+    let pair = Pair(7, b"cat".to_vec());
+    assert_eq!(decode::<Pair>(&rlp::encode(&pair)).unwrap(), pair);
+}
+
+#[test]
+fn wrong_item_count_is_rejected() {
+    let mut s = RlpStream::new_list(3);
+    s.append(&1u64).append(&2u64).append(&3u64);
+    assert_eq!(
+        decode::<Point>(&s.out()),
+        Err(DecoderError::RlpIncorrectListLen)
+    );
+}
+
+#[test]
+fn transparent_is_its_field() {
+    assert_eq!(rlp::encode(&Id(5)), rlp::encode(&5u64));
+    assert_eq!(decode::<Id>(&rlp::encode(&5u64)).unwrap(), Id(5));
+}
+
+#[test]
+fn list_and_flatten_fields() {
+    let shape = Shape {
+        id: Id(3),
+        points: vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }],
+        origin: Point { x: 0, y: 9 },
+    };
+    let mut s = RlpStream::new_list(4);
+    s.append(&3u64)
+        .append_list(&shape.points)
+        .append(&0u64)
+        .append(&9u64);
+    let expected = s.out();
+    assert_eq!(rlp::encode(&shape), expected);
+    assert_eq!(decode::<Shape>(&expected).unwrap(), shape);
+}
+
+#[test]
+fn trailing_fields_are_omitted_when_none() {
+    let short = Versioned {
+        name: "a".into(),
+        extra: None,
+        more: None,
+    };
+    let mut s = RlpStream::new_list(1);
+    s.append(&"a");
+    let expected = s.out();
+    assert_eq!(rlp::encode(&short), expected);
+    assert_eq!(decode::<Versioned>(&expected).unwrap(), short);
+
+    let long = Versioned {
+        name: "a".into(),
+        extra: Some(1),
+        more: Some(2),
+    };
+    let mut s = RlpStream::new_list(3);
+    s.append(&"a").append(&1u64).append(&2u64);
+    let expected = s.out();
+    assert_eq!(rlp::encode(&long), expected);
+    assert_eq!(decode::<Versioned>(&expected).unwrap(), long);
+
+    let mut s = RlpStream::new_list(4);
+    s.append(&"a").append(&1u64).append(&2u64).append(&3u64);
+    assert_eq!(
+        decode::<Versioned>(&s.out()),
+        Err(DecoderError::RlpIncorrectListLen)
+    );
+}
+
+#[test]
+#[should_panic(expected = "`extra` is None")]
+fn trailing_none_before_some_panics() {
+    rlp::encode(&Versioned {
+        name: "a".into(),
+        extra: None,
+        more: Some(2),
+    });
+}
@@ -67,7 +67,7 @@ use alloc::{string::String, vec::Vec};
 #[cfg(not(feature = "std"))] use core::borrow::Borrow;
 
 pub use error::DecoderError;
-pub use traits::{Decodable, Encodable, Compressible};
+pub use traits::{Decodable, Encodable, Compressible, DecodableFields, EncodableFields};
 pub use untrusted_rlp::{UntrustedRlp, UntrustedRlpIterator, PayloadInfo, Prototype};
 pub use rlpin::{Rlp, RlpIterator};
 pub use stream::RlpStream;
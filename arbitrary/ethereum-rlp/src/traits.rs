@@ -29,6 +29,26 @@ pub trait Encodable {
 	}
 }
 
+/// Structure encoded as an RLP list, the items of which can also be spliced into the list of
+/// another structure. Implemented by `#[derive(RlpEncodable)]` from `ethereum-rlp-derive`.
+pub trait EncodableFields {
+	/// Get the number of items appended by `append_fields`
+	fn field_count(&self) -> usize;
+
+	/// Append the items to the stream, without beginning a list
+	fn append_fields(&self, s: &mut RlpStream);
+}
+
+/// Structure decodable from a part of an RLP list. Implemented by `#[derive(RlpDecodable)]` from
+/// `ethereum-rlp-derive`.
+pub trait DecodableFields: Sized {
+	/// The number of items taken by `decode_fields`, not counting trailing optional items
+	const FIELD_COUNT: usize;
+
+	/// Decode a value from the items of the list starting at `offset`
+	fn decode_fields(rlp: &UntrustedRlp, offset: usize) -> Result<Self, DecoderError>;
+}
+
 /// Trait for compressing and decompressing RLP by replacement of common terms.
 pub trait Compressible: Sized {
 	/// Indicates the origin of RLP to be compressed.
@@ -0,0 +1,401 @@
+//! A write-back cache of a merkle trie.
+//!
+//! `insert` and `delete` re-encode and re-hash every node on the path to the root, for every key.
+//! The cache instead keeps the nodes it modifies decoded in memory and only hashes them once all the
+//! updates are made, so a node shared by the paths of many updated keys is hashed once.
+
+use bigint::H256;
+use rlp::{Rlp, RlpStream};
+use alloc::{boxed::Box, collections::btree_set::BTreeSet, vec::Vec};
+use core::mem;
+use merkle::{MerkleNode, MerkleValue};
+use merkle::nibble::{self, Nibble, NibbleSlice, NibbleType, NibbleVec};
+use {keccak, Change, DatabaseHandle, Error, EMPTY_TRIE_HASH};
+
+/// A node of the cached trie.
+#[derive(Default)]
+enum Node {
+    #[default]
+    Empty,
+    /// A node that was not loaded from the database.
+    Stored(H256),
+    /// A node that was loaded from the database and not modified, so it still has the given hash.
+    Cached(H256, Box<Node>),
+    Leaf(NibbleVec, Vec<u8>),
+    Extension(NibbleVec, Box<Node>),
+    Branch(Box<[Node; 16]>, Option<Vec<u8>>),
+}
+
+impl Node {
+    fn from_merkle(node: MerkleNode) -> Node {
+        match node {
+            MerkleNode::Leaf(nibble, value) => Node::Leaf(nibble, value.to_vec()),
+            MerkleNode::Extension(nibble, value) => {
+                Node::Extension(nibble, Box::new(Node::from_value(value)))
+            },
+            MerkleNode::Branch(nodes, value) => {
+                let mut children: Box<[Node; 16]> = Box::default();
+                for (child, value) in children.iter_mut().zip(IntoIterator::into_iter(nodes)) {
+                    *child = Node::from_value(value);
+                }
+                Node::Branch(children, value.map(|value| value.to_vec()))
+            },
+        }
+    }
+
+    fn from_value(value: MerkleValue) -> Node {
+        match value {
+            MerkleValue::Empty => Node::Empty,
+            MerkleValue::Hash(hash) => Node::Stored(hash),
+            MerkleValue::Full(node) => Node::from_merkle(*node),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!(*self, Node::Empty)
+    }
+}
+
+/// A write-back cache of the trie with the given root.
+///
+/// The updates only read the database. [`TrieCache::commit`] hashes the modified nodes and returns
+/// the new root and the change to apply to the database.
+pub struct TrieCache {
+    root: Node,
+    // The hashes of the loaded nodes that were modified.
+    removes: BTreeSet<H256>,
+}
+
+impl TrieCache {
+    /// Creates a cache of the trie with the given root.
+    pub fn new(root: H256) -> TrieCache {
+        TrieCache {
+            root: if root == EMPTY_TRIE_HASH { Node::Empty } else { Node::Stored(root) },
+            removes: BTreeSet::new(),
+        }
+    }
+
+    /// Inserts the value under the given key.
+    ///
+    /// If a node is missing from the database, the error is returned and the cache is unchanged.
+    pub fn insert<D: DatabaseHandle>(
+        &mut self, database: &D, key: &[u8], value: &[u8]
+    ) -> Result<(), Error> {
+        let nibble = nibble::from_key(key);
+        insert(&mut self.root, &nibble, value, database, &mut self.removes)?;
+        Ok(())
+    }
+
+    /// Deletes the given key. Does nothing if there is no such key.
+    ///
+    /// If a node is missing from the database, the error is returned and the cache is unchanged.
+    pub fn delete<D: DatabaseHandle>(&mut self, database: &D, key: &[u8]) -> Result<(), Error> {
+        let nibble = nibble::from_key(key);
+        delete(&mut self.root, &nibble, database, &mut self.removes)?;
+        Ok(())
+    }
+
+    /// Hashes the modified nodes, each once. Returns the new root and the changes.
+    pub fn commit(self) -> (H256, Change) {
+        let mut change = Change::default();
+        let root = match self.root {
+            Node::Empty => EMPTY_TRIE_HASH,
+            Node::Stored(hash) | Node::Cached(hash, _) => hash,
+            node => {
+                // The root is stored even if it is small enough to be inlined.
+                let encoded = encode(node, &mut change);
+                let hash = keccak(&encoded);
+                change.add_raw(hash, encoded);
+                hash
+            },
+        };
+        for hash in self.removes {
+            if !change.adds.contains_key(&hash) {
+                change.removes.insert(hash);
+            }
+        }
+        (root, change)
+    }
+}
+
+/// Loads the node if it is stored, so that it can be inspected. Does nothing otherwise.
+fn load<D: DatabaseHandle>(node: &mut Node, database: &D) -> Result<(), Error> {
+    if let Node::Stored(hash) = *node {
+        let decoded = MerkleNode::decode(&Rlp::new(database.get_with_error(hash)?));
+        *node = Node::Cached(hash, Box::new(Node::from_merkle(decoded)));
+    }
+    Ok(())
+}
+
+/// Takes the node out of a loaded node that is about to be modified, recording the removal of its
+/// hash.
+fn take_loaded(node: &mut Node, removes: &mut BTreeSet<H256>) -> Node {
+    match mem::take(node) {
+        Node::Cached(hash, inner) => {
+            removes.insert(hash);
+            *inner
+        },
+        node => node,
+    }
+}
+
+/// Inserts the value under the given path. Returns whether the node changed.
+///
+/// The node is only modified once everything below it succeeded, so that an error leaves it as it
+/// was.
+fn insert<D: DatabaseHandle>(
+    node: &mut Node, path: NibbleSlice, value: &[u8], database: &D,
+    removes: &mut BTreeSet<H256>
+) -> Result<bool, Error> {
+    load(node, database)?;
+    let changed = match *node {
+        Node::Empty => {
+            *node = Node::Leaf(path.into(), value.to_vec());
+            return Ok(true);
+        },
+        Node::Stored(_) => unreachable!(),
+        Node::Cached(_, ref mut inner) => {
+            return match insert(inner, path, value, database, removes)? {
+                true => {
+                    *node = take_loaded(node, removes);
+                    Ok(true)
+                },
+                false => Ok(false),
+            };
+        },
+        Node::Leaf(ref leaf_path, ref leaf_value) => {
+            if leaf_path.as_slice() == path {
+                leaf_value.as_slice() != value
+            } else {
+                true
+            }
+        },
+        Node::Extension(ref ext_path, ref mut child) => {
+            if path.starts_with(ext_path) {
+                return insert(child, &path[ext_path.len()..], value, database, removes);
+            }
+            true
+        },
+        Node::Branch(ref mut children, ref branch_value) => {
+            if path.is_empty() {
+                branch_value.as_ref().map(|v| v.as_slice()) != Some(value)
+            } else {
+                let index: usize = path[0].into();
+                return insert(&mut children[index], &path[1..], value, database, removes);
+            }
+        },
+    };
+    if !changed {
+        return Ok(false);
+    }
+
+    *node = match mem::take(node) {
+        Node::Leaf(leaf_path, leaf_value) => {
+            if leaf_path.as_slice() == path {
+                Node::Leaf(leaf_path, value.to_vec())
+            } else {
+                let common = nibble::common(&leaf_path, path).len();
+                let mut branch = Node::Branch(Box::default(), None);
+                put(&mut branch, &leaf_path[common..], Node::Leaf(Vec::new(), leaf_value));
+                put(&mut branch, &path[common..], Node::Leaf(Vec::new(), value.to_vec()));
+                extend(&path[..common], branch)
+            }
+        },
+        Node::Extension(ext_path, child) => {
+            // The path diverges from the extension, which is split at the divergence.
+            let common = nibble::common(&ext_path, path).len();
+            let mut branch = Node::Branch(Box::default(), None);
+            let rest = &ext_path[common..];
+            let index: usize = rest[0].into();
+            if let Node::Branch(ref mut children, _) = branch {
+                children[index] = extend(&rest[1..], *child);
+            }
+            put(&mut branch, &path[common..], Node::Leaf(Vec::new(), value.to_vec()));
+            extend(&path[..common], branch)
+        },
+        Node::Branch(children, _) => Node::Branch(children, Some(value.to_vec())),
+        _ => unreachable!(),
+    };
+    Ok(true)
+}
+
+/// Puts a leaf with an empty path under the given path of a branch: as the value of the branch if
+/// the path is empty, or as a leaf with the rest of the path in the child at its first nibble.
+fn put(branch: &mut Node, path: NibbleSlice, leaf: Node) {
+    let value = match leaf {
+        Node::Leaf(_, value) => value,
+        _ => unreachable!(),
+    };
+    if let Node::Branch(ref mut children, ref mut branch_value) = *branch {
+        if path.is_empty() {
+            *branch_value = Some(value);
+        } else {
+            let index: usize = path[0].into();
+            children[index] = Node::Leaf(path[1..].into(), value);
+        }
+    }
+}
+
+/// Prefixes the node with the given path: merges it into the path of a leaf or an extension, or
+/// wraps a branch into an extension.
+fn extend(prefix: NibbleSlice, node: Node) -> Node {
+    if prefix.is_empty() {
+        return node;
+    }
+    let mut path: NibbleVec = prefix.into();
+    match node {
+        Node::Leaf(rest, value) => {
+            path.extend(rest);
+            Node::Leaf(path, value)
+        },
+        Node::Extension(rest, child) => {
+            path.extend(rest);
+            Node::Extension(path, child)
+        },
+        branch => Node::Extension(path, Box::new(branch)),
+    }
+}
+
+/// Deletes the given path. Returns whether the node changed.
+///
+/// Like [`insert`], only modifies the node once everything below it succeeded. The nodes a branch
+/// may be collapsed into are loaded beforehand for that.
+fn delete<D: DatabaseHandle>(
+    node: &mut Node, path: NibbleSlice, database: &D, removes: &mut BTreeSet<H256>
+) -> Result<bool, Error> {
+    load(node, database)?;
+    let changed = match *node {
+        Node::Empty => false,
+        Node::Stored(_) => unreachable!(),
+        Node::Cached(_, ref mut inner) => {
+            return match delete(inner, path, database, removes)? {
+                true => {
+                    *node = take_loaded(node, removes);
+                    Ok(true)
+                },
+                false => Ok(false),
+            };
+        },
+        Node::Leaf(ref leaf_path, _) => leaf_path.as_slice() == path,
+        Node::Extension(ref ext_path, ref mut child) => {
+            path.starts_with(ext_path) &&
+                delete(child, &path[ext_path.len()..], database, removes)?
+        },
+        Node::Branch(ref mut children, ref mut branch_value) => {
+            let index = path.first().map(|&nibble| -> usize { nibble.into() });
+            let count = children.iter().filter(|child| !child.is_empty()).count() +
+                branch_value.iter().count();
+            if count == 2 {
+                // Deleting may leave a single child, which is then merged with the branch.
+                for (i, child) in children.iter_mut().enumerate() {
+                    if Some(i) != index {
+                        load(child, database)?;
+                    }
+                }
+            }
+            match index {
+                None => branch_value.take().is_some(),
+                Some(index) => delete(&mut children[index], &path[1..], database, removes)?,
+            }
+        },
+    };
+    if !changed {
+        return Ok(false);
+    }
+
+    *node = match mem::take(node) {
+        Node::Leaf(..) => Node::Empty,
+        Node::Extension(ext_path, child) => {
+            match *child {
+                Node::Empty => Node::Empty,
+                child => extend(&ext_path, child),
+            }
+        },
+        Node::Branch(mut children, branch_value) => collapse(&mut children, branch_value, removes),
+        _ => unreachable!(),
+    };
+    Ok(true)
+}
+
+/// Turns a branch that had an entry deleted into the equivalent canonical node.
+fn collapse(
+    children: &mut Box<[Node; 16]>, value: Option<Vec<u8>>, removes: &mut BTreeSet<H256>
+) -> Node {
+    let mut remaining = children.iter().enumerate().filter(|&(_, child)| !child.is_empty());
+    let only = match (remaining.next(), remaining.next()) {
+        (None, _) => None,
+        (Some((index, _)), None) => Some(index),
+        _ => return Node::Branch(mem::take(children), value),
+    };
+    match (only, value) {
+        (None, None) => Node::Empty,
+        (None, Some(value)) => Node::Leaf(Vec::new(), value),
+        (Some(index), None) => {
+            let prefix = [Nibble::from(index)];
+            match mem::take(&mut children[index]) {
+                // A branch keeps its hash, it is just referenced by an extension.
+                Node::Cached(hash, inner) => match *inner {
+                    branch @ Node::Branch(..) => {
+                        extend(&prefix, Node::Cached(hash, Box::new(branch)))
+                    },
+                    inner => {
+                        removes.insert(hash);
+                        extend(&prefix, inner)
+                    },
+                },
+                child => extend(&prefix, child),
+            }
+        },
+        (Some(_), Some(value)) => Node::Branch(mem::take(children), Some(value)),
+    }
+}
+
+/// Returns the RLP encoding of the node, hashing and adding to the change the children that are not
+/// inlined into it.
+fn encode(node: Node, change: &mut Change) -> Vec<u8> {
+    let mut s = RlpStream::new();
+    match node {
+        Node::Leaf(path, value) => {
+            s.begin_list(2);
+            nibble::encode(&path, NibbleType::Leaf, &mut s);
+            s.append(&value);
+        },
+        Node::Extension(path, child) => {
+            s.begin_list(2);
+            nibble::encode(&path, NibbleType::Extension, &mut s);
+            append_child(&mut s, *child, change);
+        },
+        Node::Branch(children, value) => {
+            s.begin_list(17);
+            let children: [Node; 16] = *children;
+            for child in children {
+                append_child(&mut s, child, change);
+            }
+            match value {
+                Some(value) => { s.append(&value); },
+                None => { s.append_empty_data(); },
+            }
+        },
+        Node::Empty | Node::Stored(_) | Node::Cached(..) => unreachable!(),
+    }
+    s.out()
+}
+
+/// Appends the reference to the child to the encoding of its parent: its hash, or the child itself
+/// if its encoding is shorter than a hash.
+fn append_child(s: &mut RlpStream, child: Node, change: &mut Change) {
+    match child {
+        Node::Empty => { s.append_empty_data(); },
+        Node::Stored(hash) | Node::Cached(hash, _) => { s.append(&hash); },
+        child => {
+            let encoded = encode(child, change);
+            if encoded.len() < 32 {
+                s.append_raw(&encoded, 1);
+            } else {
+                let hash = keccak(&encoded);
+                change.add_raw(hash, encoded);
+                s.append(&hash);
+            }
+        },
+    }
+}
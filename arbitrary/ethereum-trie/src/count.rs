@@ -0,0 +1,44 @@
+//! Counting of the node hashes, to measure the hashing work of trie operations.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of bytes absorbed per keccak-f permutation by Keccak-256.
+const RATE: usize = 136;
+
+static HASHES: AtomicUsize = AtomicUsize::new(0);
+static PERMUTATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The node hashes computed so far by the process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HashCount {
+    /// The number of nodes hashed.
+    pub hashes: usize,
+    /// The number of keccak-f permutations run to hash them.
+    pub permutations: usize,
+}
+
+impl core::ops::Sub for HashCount {
+    type Output = HashCount;
+
+    fn sub(self, other: HashCount) -> HashCount {
+        HashCount {
+            hashes: self.hashes - other.hashes,
+            permutations: self.permutations - other.permutations,
+        }
+    }
+}
+
+/// Get the node hashes computed so far. The count is global, take the difference of two counts to
+/// measure an operation.
+pub fn hash_count() -> HashCount {
+    HashCount {
+        hashes: HASHES.load(Ordering::Relaxed),
+        permutations: PERMUTATIONS.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn record(len: usize) {
+    HASHES.fetch_add(1, Ordering::Relaxed);
+    // The padding takes at least one byte, so it adds a block if the input fills the last one.
+    PERMUTATIONS.fetch_add(len / RATE + 1, Ordering::Relaxed);
+}
@@ -38,9 +38,23 @@ pub const EMPTY_TRIE_HASH: H256 = H256([0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55
 pub mod merkle;
 mod ops;
 mod error;
+mod cache;
+#[cfg(feature = "hash-count")]
+mod count;
 
 use ops::{insert, delete, build, get};
 pub use error::Error;
+pub use cache::TrieCache;
+#[cfg(feature = "hash-count")]
+pub use count::{hash_count, HashCount};
+
+/// Hash an encoded node. All the node hashes of the crate are computed here, so that they can be
+/// counted.
+fn keccak(encoded: &[u8]) -> H256 {
+    #[cfg(feature = "hash-count")]
+    count::record(encoded.len());
+    H256::from(Keccak256::digest(encoded).as_slice())
+}
 
 /// An immutable database handle.
 pub trait DatabaseHandle {
@@ -88,7 +102,7 @@ impl Change {
     /// Change to add a new node.
     pub fn add_node<'a, 'b, 'c>(&'a mut self, node: &'c MerkleNode<'b>) {
         let subnode = rlp::encode(node).to_vec();
-        let hash = H256::from(Keccak256::digest(&subnode).as_slice());
+        let hash = keccak(&subnode);
         self.add_raw(hash, subnode);
     }
 
@@ -98,7 +112,7 @@ impl Change {
             MerkleValue::Full(Box::new(node.clone()))
         } else {
             let subnode = rlp::encode(node).to_vec();
-            let hash = H256::from(Keccak256::digest(&subnode).as_slice());
+            let hash = keccak(&subnode);
             self.add_raw(hash, subnode);
             MerkleValue::Hash(hash)
         }
@@ -117,7 +131,7 @@ impl Change {
             false
         } else {
             let subnode = rlp::encode(node).to_vec();
-            let hash = H256::from(Keccak256::digest(&subnode).as_slice());
+            let hash = keccak(&subnode);
             self.remove_raw(hash);
             true
         }
@@ -152,7 +166,7 @@ pub fn insert<D: DatabaseHandle>(
     change.merge(&subchange);
     change.add_node(&new);
 
-    let hash = H256::from(Keccak256::digest(&rlp::encode(&new).to_vec()).as_slice());
+    let hash = keccak(&rlp::encode(&new));
     Ok((hash, change))
 }
 
@@ -168,7 +182,7 @@ pub fn insert_empty<D: DatabaseHandle>(
     change.merge(&subchange);
     change.add_node(&new);
 
-    let hash = H256::from(Keccak256::digest(&rlp::encode(&new).to_vec()).as_slice());
+    let hash = keccak(&rlp::encode(&new));
     (hash, change)
 }
 
@@ -193,7 +207,7 @@ pub fn delete<D: DatabaseHandle>(
         Some(new) => {
             change.add_node(&new);
 
-            let hash = H256::from(Keccak256::digest(&rlp::encode(&new).to_vec()).as_slice());
+            let hash = keccak(&rlp::encode(&new));
             Ok((hash, change))
         },
         None => {
@@ -220,7 +234,7 @@ pub fn build(map: &BTreeMap<Vec<u8>, Vec<u8>>) -> (H256, Change) {
     change.merge(&subchange);
     change.add_node(&node);
 
-    let hash = H256::from(Keccak256::digest(&rlp::encode(&node).to_vec()).as_slice());
+    let hash = keccak(&rlp::encode(&node));
     (hash, change)
 }
 
@@ -0,0 +1,118 @@
+extern crate bigint;
+extern crate trie;
+
+use std::collections::BTreeMap;
+use bigint::H256;
+use trie::{Change, TrieCache, EMPTY_TRIE_HASH};
+
+/// A database that keeps every node ever added, so that all the roots can be read.
+#[derive(Default)]
+struct Database(BTreeMap<H256, Vec<u8>>);
+
+impl Database {
+    fn apply(&mut self, change: Change) {
+        self.0.extend(change.adds);
+    }
+}
+
+/// Keys of different lengths sharing prefixes, so that the tries have every kind of node.
+fn keys() -> Vec<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut seed = 7u32;
+    for i in 0..300u32 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let bytes = seed.to_be_bytes();
+        keys.push(bytes[..(i % 4 + 1) as usize].to_vec());
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+/// Applies the updates one by one with `trie::insert` and `trie::delete`.
+fn sequential(
+    database: &mut Database, mut root: H256, updates: &[(Vec<u8>, Option<Vec<u8>>)]
+) -> H256 {
+    for (key, value) in updates {
+        let (new_root, change) = match value {
+            Some(value) => trie::insert(root, &&database.0, key, value).unwrap(),
+            None => trie::delete(root, &&database.0, key).unwrap(),
+        };
+        database.apply(change);
+        root = new_root;
+    }
+    root
+}
+
+/// Applies the updates through a cache.
+fn cached(
+    database: &mut Database, root: H256, updates: &[(Vec<u8>, Option<Vec<u8>>)]
+) -> H256 {
+    let mut cache = TrieCache::new(root);
+    for (key, value) in updates {
+        match value {
+            Some(value) => cache.insert(&&database.0, key, value).unwrap(),
+            None => cache.delete(&&database.0, key).unwrap(),
+        }
+    }
+    let (root, change) = cache.commit();
+    database.apply(change);
+    root
+}
+
+/// Reads all the keys from the trie with the given root.
+fn read_all(database: &Database, root: H256, keys: &[Vec<u8>]) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    keys.iter().filter_map(|key| {
+        trie::get(root, &&database.0, key).unwrap().map(|value| (key.clone(), value.to_vec()))
+    }).collect()
+}
+
+#[test]
+fn cache_matches_sequential_updates() {
+    let keys = keys();
+    // Short values get inlined into their parents, long ones don't.
+    let inserts: Vec<_> = keys.iter().enumerate().map(|(i, key)| {
+        (key.clone(), Some(vec![i as u8; i % 40 + 1]))
+    }).collect();
+    // Overwrite, delete and re-insert some of the keys, in a different order.
+    let updates: Vec<_> = keys.iter().enumerate().rev().filter_map(|(i, key)| match i % 3 {
+        0 => Some((key.clone(), None)),
+        1 => Some((key.clone(), Some(vec![0xff; i % 7 + 1]))),
+        _ => None,
+    }).chain(vec![(keys[0].clone(), Some(vec![1])), (vec![0xab; 9], None)]).collect();
+    let deletes: Vec<_> = keys.iter().map(|key| (key.clone(), None)).collect();
+
+    let mut database = Database::default();
+    let mut root = EMPTY_TRIE_HASH;
+    for batch in &[inserts, updates, deletes] {
+        let expected = sequential(&mut database, root, batch);
+        root = cached(&mut database, root, batch);
+        assert_eq!(root, expected);
+        assert_eq!(root, trie::build(&read_all(&database, root, &keys)).0);
+    }
+    assert_eq!(root, EMPTY_TRIE_HASH);
+}
+
+#[test]
+fn missing_node_leaves_cache_unchanged() {
+    let mut database = Database::default();
+    let updates: Vec<_> = (0..16u8).map(|i| (vec![i * 16, i], Some(vec![i; 40]))).collect();
+    let root = cached(&mut database, EMPTY_TRIE_HASH, &updates);
+    // Only the root branch, the leaves under it are missing.
+    let mut partial = Database::default();
+    partial.0.insert(root, database.0[&root].clone());
+
+    let mut cache = TrieCache::new(root);
+    match cache.insert(&&partial.0, &[0x10, 1], &[3]) {
+        Err(trie::Error::Require(hash)) => assert!(hash != root),
+        Ok(()) => panic!("the leaf is missing"),
+    }
+    match cache.delete(&&partial.0, &[0x20, 2]) {
+        Err(trie::Error::Require(hash)) => assert!(hash != root),
+        Ok(()) => panic!("the leaf is missing"),
+    }
+    cache.insert(&&database.0, &[0x10, 1], &[3]).unwrap();
+
+    let expected = sequential(&mut database, root, &[(vec![0x10, 1], Some(vec![3]))]);
+    assert_eq!(cache.commit().0, expected);
+}